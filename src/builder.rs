@@ -1,21 +1,46 @@
+use crate::github::{delete_asset, set_commit_status, upload_asset, CommitState, Github};
+use crate::lang::{get_build_template, get_language, BuildTemplate};
+use crate::output::{copy_output, get_last_line, BuildOutput, Stream};
 use crate::pot::{
-    destroy_fscomp, destroy_pot, get_pot_path, is_pot_present, revert_fscomp, spawn_builder_pot,
-    PotError,
+    clone_fscomp, create_cache_fscomp, destroy_fscomp, destroy_pot, get_pot_path, is_pot_present,
+    mount_in_pot, revert_fscomp, spawn_builder_pot, stop_pot, PotError,
 };
 use crate::{BuildJob, BuildOpt, Opt, Project};
+use chrono::{DateTime, Local};
 use failure::{Error, Fail};
-use log::debug;
+use log::{debug, warn};
+use serde_derive::{Deserialize, Serialize};
+use std::fmt::Display;
 use std::fs::File;
 use std::io::Write;
 use std::os::unix::fs::PermissionsExt;
-use std::path::PathBuf;
-use std::process::Command;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
 use tera::{Context, Tera};
 
 #[derive(Debug, Fail)]
 pub(crate) enum BuildError {
     #[fail(display = "Build failed: {}", potname)]
     BuildFailed { potname: String },
+    #[fail(display = "Build timed out after {} minutes: {}", minutes, potname)]
+    TimedOut { potname: String, minutes: u64 },
+    #[fail(
+        display = "Build stalled, no output for {} minutes: {}\nlast lines of output:\n{}",
+        minutes, potname, tail
+    )]
+    Stalled {
+        potname: String,
+        minutes: u64,
+        tail: String,
+    },
+    #[fail(display = "{} job(s) failed", failed)]
+    JobsFailed { failed: usize },
+    #[fail(display = "Build artifact not found: {}", path)]
+    ArtifactNotFound { path: String },
     #[fail(display = "Missing pot: {}", potname)]
     PotNotPresent { potname: String },
     #[fail(display = "Tera template parsing error: {}", msg)]
@@ -24,28 +49,43 @@ pub(crate) enum BuildError {
     TeraTemplateRenderingErr { msg: String },
 }
 
-fn generate_build_script(
-    pot_name: &str,
+/// The directory, in the pot, where the build script leaves the tarball
+const ARTIFACTS_DIR: &str = "/root/artifacts";
+
+/// The name of the tarball deployed by the job
+pub(crate) fn get_tarball_name(job: &BuildJob, prj: &Project) -> String {
+    format!(
+        "{}-{}-{}.tar.gz",
+        job.os.os_family, job.os.os_version, prj.project
+    )
+}
+
+/// Render the build script template for the given job
+pub(crate) fn render_build_script(
     job: &BuildJob,
     prj: &Project,
     build_opt: &BuildOpt,
-    token: &str,
-    opt: &Opt,
-) -> Result<(), Error> {
-    let mut template_dir = opt
-        .build_template
-        .parent()
-        .unwrap()
-        .to_str()
-        .unwrap()
-        .to_string();
-    if &template_dir == "" {
-        template_dir.push_str(".");
-    }
-    template_dir.push_str("/*");
-    debug!("template directory: {}", template_dir);
+    build_template: &BuildTemplate,
+) -> Result<String, Error> {
+    let (tera, template_name) = match build_template {
+        BuildTemplate::File(path) => {
+            let mut template_dir = path.parent().unwrap().to_str().unwrap().to_string();
+            if &template_dir == "" {
+                template_dir.push_str(".");
+            }
+            template_dir.push_str("/*");
+            debug!("template directory: {}", template_dir);
+            let name = path.file_name().unwrap().to_str().unwrap().to_string();
+            (Tera::new(&template_dir), name)
+        }
+        BuildTemplate::Embedded { name, source } => {
+            let mut tera = Tera::default();
+            let result = tera.add_raw_template(name, source).map(|_| tera);
+            (result, name.clone())
+        }
+    };
     // render the template
-    let tera = match Tera::new(&template_dir) {
+    let tera = match tera {
         Ok(t) => t,
         Err(e) => {
             return Err(Error::from(BuildError::TeraTemplateParseErr {
@@ -53,10 +93,7 @@ fn generate_build_script(
             }));
         }
     };
-    let tarball = format!(
-        "{}-{}-{}.tar.gz",
-        job.os.os_family, job.os.os_version, prj.project
-    );
+    let tarball = get_tarball_name(job, prj);
     let mut context = Context::new();
     context.insert("update", &build_opt.update);
     context.insert("language", &job.lang.lang);
@@ -66,35 +103,31 @@ fn generate_build_script(
     context.insert("user", &prj.owner);
     context.insert("project", &prj.project);
     context.insert("tarball", &tarball);
-    if let Some(release_id) = build_opt.release_id {
-        context.insert("upload", &job.deploy);
-        context.insert("token", token);
-        context.insert("release_id", &release_id);
-        if let Some(a) = build_opt.assets.iter().find(|x| x.name == tarball) {
-            context.insert("delete_asset", &true);
-            context.insert("asset_id", &a.id);
-        } else {
-            context.insert("delete_asset", &false);
-            context.insert("asset_id", &0);
-        }
-    } else {
-        context.insert("upload", &false);
-        context.insert("token", "");
-        context.insert("release_id", &0);
-        context.insert("delete_asset", &false);
-        context.insert("asset_id", &0);
-    }
-    let script = match tera.render(
-        opt.build_template.file_name().unwrap().to_str().unwrap(),
-        &context,
-    ) {
-        Ok(s) => s,
-        Err(e) => {
-            return Err(Error::from(BuildError::TeraTemplateRenderingErr {
-                msg: format!("{}", e),
-            }));
-        }
-    };
+    context.insert("sha", build_opt.sha.as_ref().map_or("", String::as_str));
+    context.insert("env", &job.env);
+    context.insert(
+        "build_system",
+        build_opt.build_system.as_ref().map_or("", String::as_str),
+    );
+    context.insert("upload", &(job.deploy && build_opt.release_id.is_some()));
+    context.insert("artifacts_dir", ARTIFACTS_DIR);
+    match tera.render(&template_name, &context) {
+        Ok(s) => Ok(s),
+        Err(e) => Err(Error::from(BuildError::TeraTemplateRenderingErr {
+            msg: format!("{}", e),
+        })),
+    }
+}
+
+fn generate_build_script(
+    pot_name: &str,
+    job: &BuildJob,
+    prj: &Project,
+    build_opt: &BuildOpt,
+    opt: &Opt,
+) -> Result<(), Error> {
+    let build_template = get_build_template(opt.build_template.as_deref(), job);
+    let script = render_build_script(job, prj, build_opt, &build_template)?;
     if opt.render_build_flag {
         println!("{}", script);
     } else {
@@ -115,10 +148,33 @@ fn generate_build_script(
     Ok(())
 }
 
-fn run_build_script(pot_name: &str) -> Result<(), Error> {
+/// A number of minutes as a duration; none if it's too long to be represented,
+/// as if there was no limit
+fn get_minutes(minutes: u64) -> Option<Duration> {
+    minutes.checked_mul(60).map(Duration::from_secs)
+}
+
+/// Wait for the output of the build to be copied to the log files
+fn join_copies(copies: [thread::JoinHandle<std::io::Result<()>>; 2]) -> Result<(), Error> {
+    for c in copies {
+        c.join().unwrap()?;
+    }
+    Ok(())
+}
+
+/// Run the build script of the job in the pot, streaming its output; after
+/// `timeout` minutes, if any, or after `stall_timeout` minutes without any
+/// output, if any, the build is killed and the logs written so far are kept
+fn run_build_script(
+    job: &BuildJob,
+    pot_name: &str,
+    log_dir: &Path,
+    timeout: Option<u64>,
+    stall_timeout: Option<u64>,
+) -> Result<(), Error> {
     println!("Start the build on {}", pot_name);
     let output = Command::new("pot")
-        .args(&["set-cmd", "-p", &pot_name, "-c", "/root/build.sh"])
+        .args(&["set-cmd", "-p", pot_name, "-c", "/root/build.sh"])
         .output()?;
     if !output.status.success() {
         return Err(Error::from(PotError::PotStartFailed {
@@ -126,23 +182,88 @@ fn run_build_script(pot_name: &str) -> Result<(), Error> {
         }));
     }
 
-    let output = Command::new("pot").args(&["start", &pot_name]).output()?;
     // write the log somewhere
-    let mut log_filename = pot_name.to_string();
-    log_filename.push_str(".log");
-    let mut log_file = File::create(&log_filename)?;
-    log_file.write_all(&output.stdout)?;
-
-    let mut logerr_filename = pot_name.to_string();
-    logerr_filename.push_str("_err.log");
-    let mut logerr_file = File::create(&logerr_filename)?;
-    logerr_file.write_all(&output.stderr)?;
+    let log_filename = get_log_path(log_dir, job, "");
+    let log_file = File::create(&log_filename)?;
+    let logerr_filename = get_log_path(log_dir, job, "_err");
+    let logerr_file = File::create(&logerr_filename)?;
+    let combined_filename = get_log_path(log_dir, job, "_combined");
+    let build_output = BuildOutput::new(&job.to_string(), File::create(&combined_filename)?);
+    let mut child = Command::new("pot")
+        .args(&["start", pot_name])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let copies = [
+        copy_output(
+            child.stdout.take().unwrap(),
+            Stream::Stdout,
+            log_file,
+            build_output.clone(),
+        ),
+        copy_output(
+            child.stderr.take().unwrap(),
+            Stream::Stderr,
+            logerr_file,
+            build_output.clone(),
+        ),
+    ];
+    let deadline = timeout
+        .and_then(get_minutes)
+        .and_then(|d| Instant::now().checked_add(d));
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        let error = if deadline.iter().any(|d| Instant::now() >= *d) {
+            let minutes = timeout.unwrap_or_default();
+            println!("Build TIMED OUT after {} minutes", minutes);
+            Some(BuildError::TimedOut {
+                potname: pot_name.to_string(),
+                minutes,
+            })
+        } else {
+            match stall_timeout {
+                Some(minutes)
+                    if get_minutes(minutes)
+                        .iter()
+                        .any(|d| build_output.idle() >= *d) =>
+                {
+                    println!("Build STALLED, no output for {} minutes", minutes);
+                    Some(BuildError::Stalled {
+                        potname: pot_name.to_string(),
+                        minutes,
+                        tail: build_output.tail().join("\n"),
+                    })
+                }
+                _ => None,
+            }
+        };
+        if let Some(e) = error {
+            child.kill()?;
+            child.wait()?;
+            // the processes of the pot keep the output open until it's stopped
+            if let Err(e) = stop_pot(pot_name) {
+                warn!("Failed to stop the pot {}: {}", pot_name, e);
+            }
+            join_copies(copies)?;
+            println!(
+                "log files\n  - {}\n  - {}\n  - {}",
+                log_filename.display(),
+                logerr_filename.display(),
+                combined_filename.display()
+            );
+            return Err(Error::from(e));
+        }
+        thread::sleep(Duration::from_secs(1));
+    };
+    join_copies(copies)?;
     println!(
-        "Build {}; log files\n  - {}\n  - {}",
-        match output.status.success() { true => "succeeded", false => "FAILED" },
-        log_filename, logerr_filename
+        "Build {}; log files\n  - {}\n  - {}\n  - {}",
+        match status.success() { true => "succeeded", false => "FAILED" },
+        log_filename.display(), logerr_filename.display(), combined_filename.display()
     );
-    if !output.status.success() {
+    if !status.success() {
         return Err(Error::from(BuildError::BuildFailed {
             potname: pot_name.to_string(),
         }));
@@ -150,44 +271,614 @@ fn run_build_script(pot_name: &str) -> Result<(), Error> {
     Ok(())
 }
 
-pub(crate) fn build(
-    queue: &[BuildJob],
+/// The log file of the job in the log directory; `suffix` selects the
+/// stream: "" for stdout, "_err" for stderr, "_combined" for both
+pub(crate) fn get_log_path(log_dir: &Path, job: &BuildJob, suffix: &str) -> PathBuf {
+    log_dir.join(format!("{}{}.log", job.to_string(), suffix))
+}
+
+/// The outcome of a job
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum JobStatus {
+    Succeeded,
+    Failed,
+    FailedAllowed,
+    TimedOut,
+    Stalled,
+    /// The job couldn't be built, because of pot
+    Error,
+    Skipped,
+}
+
+impl Display for JobStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let s = match self {
+            JobStatus::Succeeded => "succeeded",
+            JobStatus::Failed => "FAILED",
+            JobStatus::FailedAllowed => "failed (allowed)",
+            JobStatus::TimedOut => "TIMED OUT",
+            JobStatus::Stalled => "STALLED",
+            JobStatus::Error => "ERROR",
+            JobStatus::Skipped => "skipped",
+        };
+        f.pad(s)
+    }
+}
+
+/// The outcome of a job, with its start, its duration and its log file, if any
+#[derive(Debug)]
+pub(crate) struct JobReport<'a> {
+    pub(crate) job: &'a BuildJob,
+    pub(crate) status: JobStatus,
+    pub(crate) started: Option<DateTime<Local>>,
+    pub(crate) duration: Duration,
+    pub(crate) log: Option<String>,
+}
+
+impl JobStatus {
+    /// If the job failed and its failure is not allowed
+    pub(crate) fn is_failed(self) -> bool {
+        matches!(
+            self,
+            JobStatus::Failed | JobStatus::TimedOut | JobStatus::Stalled | JobStatus::Error
+        )
+    }
+}
+
+pub(crate) fn format_duration(d: Duration) -> String {
+    let secs = d.as_secs();
+    format!("{}m{:02}s", secs / 60, secs % 60)
+}
+
+/// A table, with the columns aligned
+pub(crate) fn format_table(header: &[&str], rows: &[Vec<String>]) -> String {
+    let mut widths: Vec<usize> = header.iter().map(|h| h.len()).collect();
+    for row in rows {
+        for (w, c) in widths.iter_mut().zip(row.iter()) {
+            *w = (*w).max(c.len());
+        }
+    }
+    let mut rc = String::new();
+    let header: Vec<String> = header.iter().map(|h| h.to_string()).collect();
+    for row in std::iter::once(&header).chain(rows.iter()) {
+        let line: Vec<String> = row
+            .iter()
+            .zip(widths.iter())
+            .map(|(c, w)| format!("{:w$}", c, w = w))
+            .collect();
+        rc.push_str(line.join("  ").trim_end());
+        rc.push('\n');
+    }
+    rc
+}
+
+/// The table with the outcome of every job
+pub(crate) fn format_summary(reports: &[JobReport]) -> String {
+    let rows: Vec<Vec<String>> = reports
+        .iter()
+        .map(|r| {
+            vec![
+                r.job.os.os_family.clone(),
+                r.job.os.os_version.clone(),
+                r.job.lang.lang.clone(),
+                r.job.lang.lang_variant.clone(),
+                r.status.to_string(),
+                format_duration(r.duration),
+                r.log.clone().unwrap_or_else(|| "-".to_string()),
+            ]
+        })
+        .collect();
+    format_table(
+        &["OS", "VERSION", "LANG", "VARIANT", "STATUS", "TIME", "LOG"],
+        &rows,
+    )
+}
+
+/// Fail if any job, whose failure is not allowed, failed
+pub(crate) fn check_reports(reports: &[JobReport]) -> Result<(), Error> {
+    let failed = reports.iter().filter(|r| r.status.is_failed()).count();
+    if failed > 0 {
+        return Err(Error::from(BuildError::JobsFailed { failed }));
+    }
+    Ok(())
+}
+
+/// If the build script failed, timed out or stalled, as opposed to an error
+/// of pot
+fn is_build_failed(e: &Error) -> bool {
+    matches!(
+        e.downcast_ref::<BuildError>(),
+        Some(BuildError::BuildFailed { .. })
+            | Some(BuildError::TimedOut { .. })
+            | Some(BuildError::Stalled { .. })
+    )
+}
+
+/// The status of a job failed with the given error
+fn get_failed_status(e: &Error) -> JobStatus {
+    match e.downcast_ref::<BuildError>() {
+        Some(BuildError::TimedOut { .. }) => JobStatus::TimedOut,
+        Some(BuildError::Stalled { .. }) => JobStatus::Stalled,
+        _ => JobStatus::Failed,
+    }
+}
+
+/// Build a job in a new pot, with the given fscomp mounted in; the pot is
+/// destroyed at the end
+fn build_job(
+    b: &BuildJob,
+    image_name: &str,
+    fscomp_name: &str,
     prj: &Project,
     opt: &Opt,
     build_opt: &BuildOpt,
-    token: &str,
+    gh: &Github,
 ) -> Result<(), Error> {
-    let fscomp_name = prj.to_string();
-    for b in queue {
-        let image_name = b.to_string();
-        if !is_pot_present(&image_name) {
-            return Err(Error::from(BuildError::PotNotPresent {
-                potname: image_name,
-            }));
-        }
+    if !is_pot_present(image_name) {
+        return Err(Error::from(BuildError::PotNotPresent {
+            potname: image_name.to_string(),
+        }));
+    }
 
-        // spawn the container
-        let pot_name = spawn_builder_pot(&image_name, &fscomp_name, &opt)?;
-        println!("\nSpawned new pot: {}", pot_name);
-        // run the build
-        generate_build_script(&pot_name, b, prj, build_opt, &token, opt)?;
-        if opt.render_build_flag {
-            destroy_pot(&pot_name)?;
-            destroy_fscomp(&fscomp_name)?;
-            return Ok(());
-        }
-        let build_result = run_build_script(&pot_name);
-        // cleanup
-        // // destroy the pot
+    // spawn the container
+    let pot_name = spawn_builder_pot(image_name, fscomp_name, opt)?;
+    println!("\nSpawned new pot: {}", pot_name);
+    if let Some((cache, mnt)) = get_language(&b.lang.lang).and_then(|l| l.cache_fscomp()) {
+        mount_in_pot(&pot_name, cache, mnt)?;
+    }
+    if let Some(sha) = &build_opt.sha {
+        println!("Building commit {}", sha);
+    }
+    // run the build
+    generate_build_script(&pot_name, b, prj, build_opt, opt)?;
+    if opt.render_build_flag {
         destroy_pot(&pot_name)?;
-        debug!("Destroyed pot: {}", pot_name);
+        return Ok(());
+    }
+    let build_result = run_build_script(
+        b,
+        &pot_name,
+        &build_opt.log_dir,
+        b.timeout.or(build_opt.timeout),
+        build_opt.stall_timeout,
+    )
+    .and_then(|_| deploy_artifact(b, &pot_name, prj, build_opt, gh));
+    // cleanup
+    // // destroy the pot
+    destroy_pot(&pot_name)?;
+    debug!("Destroyed pot: {}", pot_name);
+
+    build_result
+}
+
+/// The tarball left by the build script in the artifacts directory of the
+/// pot, seen from the host
+fn get_artifact_path(pot_path: &Path, tarball: &str) -> Result<PathBuf, Error> {
+    let path = pot_path
+        .join("m")
+        .join(ARTIFACTS_DIR.trim_start_matches('/'))
+        .join(tarball);
+    if !path.is_file() {
+        return Err(Error::from(BuildError::ArtifactNotFound {
+            path: path.display().to_string(),
+        }));
+    }
+    Ok(path)
+}
+
+/// Upload the tarball left by the build script to the release, replacing
+/// the asset with the same name, if any; the token never enters the pot
+fn deploy_artifact(
+    b: &BuildJob,
+    pot_name: &str,
+    prj: &Project,
+    build_opt: &BuildOpt,
+    gh: &Github,
+) -> Result<(), Error> {
+    let release_id = match build_opt.release_id {
+        Some(release_id) if b.deploy => release_id,
+        _ => return Ok(()),
+    };
+    let tarball = get_tarball_name(b, prj);
+    let path = get_artifact_path(Path::new(&get_pot_path(pot_name)?), &tarball)?;
+    if let Some(a) = build_opt.assets.iter().find(|a| a.name == tarball) {
+        println!("Deleting the old asset {}", tarball);
+        delete_asset(prj, a.id, gh)?;
+    }
+    println!("Uploading the asset {}", tarball);
+    upload_asset(prj, release_id, &path, gh)?;
+    Ok(())
+}
+
+/// The name of the pot image used to build the job
+pub(crate) fn get_image_name(b: &BuildJob) -> String {
+    b.to_string()
+}
+
+/// The commit status of a finished job: the allowed failures don't fail the
+/// commit and the failures are described by the last line of output, if any
+fn get_commit_status(
+    status: JobStatus,
+    duration: Duration,
+    excerpt: Option<&str>,
+) -> (CommitState, String) {
+    let state = match status {
+        JobStatus::Succeeded | JobStatus::FailedAllowed => CommitState::Success,
+        JobStatus::Failed | JobStatus::TimedOut | JobStatus::Stalled => CommitState::Failure,
+        JobStatus::Error | JobStatus::Skipped => CommitState::Error,
+    };
+    let mut description = format!("{} in {}", status, format_duration(duration));
+    if let (CommitState::Failure, Some(line)) = (state, excerpt) {
+        description.push_str(": ");
+        description.push_str(line);
+    }
+    (state, description)
+}
+
+/// Report the state of the job as a commit status, if requested; a failure
+/// is only logged, as it doesn't affect the build
+fn report_commit_status(
+    b: &BuildJob,
+    prj: &Project,
+    build_opt: &BuildOpt,
+    gh: &Github,
+    state: CommitState,
+    description: &str,
+) {
+    if let Some(sha) = &build_opt.status_sha {
+        let context = format!("freebsd-ci/{}", b.to_string());
+        if let Err(e) = set_commit_status(prj, sha, gh, &context, state, description) {
+            warn!("Failed to set the commit status {}: {}", context, e);
+        }
+    }
+}
+
+/// Run the job with index `index` in the queue and report its outcome; an
+/// error is returned only if the build has to stop
+///
+/// With parallel jobs, every job builds a clone of the project fscomp,
+/// destroyed at the end; otherwise the project fscomp is reverted
+///
+/// An error of pot, that stops the build, is returned with the report
+fn run_job<'a>(
+    index: usize,
+    b: &'a BuildJob,
+    prj: &Project,
+    opt: &Opt,
+    build_opt: &BuildOpt,
+    gh: &Github,
+) -> (JobReport<'a>, Option<Error>) {
+    let image_name = get_image_name(b);
+    let fscomp_name = prj.to_string();
+    report_commit_status(
+        b,
+        prj,
+        build_opt,
+        gh,
+        CommitState::Pending,
+        &format!(
+            "Building on {} {}, {} {}",
+            b.os.os_family, b.os.os_version, b.lang.lang, b.lang.lang_variant
+        ),
+    );
+    let started = Local::now();
+    let start = Instant::now();
+    let result = if opt.jobs > 1 {
+        let job_fscomp = format!("{}-{}", fscomp_name, index);
+        clone_fscomp(&fscomp_name, &job_fscomp, opt).and_then(|_| {
+            let result = build_job(b, &image_name, &job_fscomp, prj, opt, build_opt, gh);
+            result.and(destroy_fscomp(&job_fscomp))
+        })
+    } else {
+        let result = build_job(b, &image_name, &fscomp_name, prj, opt, build_opt, gh);
         // // revert the fscomp
-        revert_fscomp(&fscomp_name)?;
+        let result = result.and(revert_fscomp(&fscomp_name));
         debug!("Revert fscomp : {}", fscomp_name);
+        result
+    };
+    let log = get_log_path(&build_opt.log_dir, b, "");
+    let mut report = JobReport {
+        job: b,
+        status: JobStatus::Succeeded,
+        started: Some(started),
+        duration: start.elapsed(),
+        log: Some(log)
+            .filter(|l| l.exists())
+            .map(|l| l.display().to_string()),
+    };
+    if let Err(e) = result {
+        let failed = get_failed_status(&e);
+        if b.allow_failure && is_build_failed(&e) {
+            println!("Job {} failed (allowed)", b.to_string());
+            report.status = JobStatus::FailedAllowed;
+        } else if is_build_failed(&e) {
+            println!("Job {} failed: {}", b.to_string(), e);
+            report.status = failed;
+        } else {
+            println!("Job {} stopped by an error: {}", b.to_string(), e);
+            report.status = JobStatus::Error;
+            report_commit_status(b, prj, build_opt, gh, CommitState::Error, &e.to_string());
+            return (report, Some(e));
+        }
+    }
+    let excerpt = get_last_line(&get_log_path(&build_opt.log_dir, b, "_combined"));
+    let (state, description) =
+        get_commit_status(report.status, report.duration, excerpt.as_deref());
+    report_commit_status(b, prj, build_opt, gh, state, &description);
+    (report, None)
+}
 
-        build_result?;
+/// Build the jobs of the queue, up to `opt.jobs` at the same time, and
+/// return their outcome, in the order of the queue
+///
+/// By default, the first failed job stops the build and the jobs not yet
+/// started are skipped; with the keep-going flag, all the jobs are built.
+/// An error of pot stops the build too: it's returned with the outcome of
+/// all the jobs
+pub(crate) fn build<'a>(
+    queue: &'a [BuildJob],
+    prj: &Project,
+    opt: &Opt,
+    build_opt: &BuildOpt,
+    gh: &Github,
+) -> (Vec<JobReport<'a>>, Option<Error>) {
+    let fscomp_name = prj.to_string();
+    // the caches are shared by the jobs
+    for b in queue {
+        if let Some((cache, _)) = get_language(&b.lang.lang).and_then(|l| l.cache_fscomp()) {
+            if let Err(e) = create_cache_fscomp(cache) {
+                return (Vec::new(), Some(e));
+            }
+        }
+    }
+    if opt.render_build_flag {
+        let result = match queue.first() {
+            Some(b) => build_job(b, &get_image_name(b), &fscomp_name, prj, opt, build_opt, gh),
+            None => Ok(()),
+        };
+        return (Vec::new(), result.and(destroy_fscomp(&fscomp_name)).err());
+    }
+    let next = AtomicUsize::new(0);
+    let stopped = AtomicBool::new(false);
+    let reports: Mutex<Vec<Option<JobReport>>> = Mutex::new(queue.iter().map(|_| None).collect());
+    let error = Mutex::new(None);
+    thread::scope(|s| {
+        for _ in 0..opt.jobs.max(1) {
+            s.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::SeqCst);
+                let b = match queue.get(index) {
+                    Some(b) => b,
+                    None => break,
+                };
+                let report = if stopped.load(Ordering::SeqCst) {
+                    JobReport {
+                        job: b,
+                        status: JobStatus::Skipped,
+                        started: None,
+                        duration: Duration::default(),
+                        log: None,
+                    }
+                } else {
+                    let (report, e) = run_job(index, b, prj, opt, build_opt, gh);
+                    if let Some(e) = e {
+                        stopped.store(true, Ordering::SeqCst);
+                        error.lock().unwrap().get_or_insert(e);
+                    } else if report.status.is_failed() && !opt.keep_going_flag {
+                        stopped.store(true, Ordering::SeqCst);
+                    }
+                    report
+                };
+                reports.lock().unwrap()[index] = Some(report);
+            });
+        }
+    });
+    let reports = reports
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .flatten()
+        .collect();
+    let error = match error.into_inner().unwrap() {
+        Some(e) => Some(e),
+        // destroy the fscomp
+        None => destroy_fscomp(&fscomp_name).err(),
+    };
+    (reports, error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BuildOS;
+    use structopt::StructOpt;
+
+    fn job(variant: &str) -> BuildJob {
+        BuildJob::test("rust", variant)
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(Duration::from_secs(7)), "0m07s");
+        assert_eq!(format_duration(Duration::from_secs(3725)), "62m05s");
+    }
+    #[test]
+    fn test_get_minutes() {
+        assert_eq!(get_minutes(2), Some(Duration::from_secs(120)));
+        assert_eq!(get_minutes(u64::MAX), None);
+        // too far to be a deadline
+        let deadline = get_minutes(u64::MAX / 60).and_then(|d| Instant::now().checked_add(d));
+        assert_eq!(deadline, None);
+    }
+    #[test]
+    fn test_format_summary() {
+        let (stable, nightly) = (job("stable"), job("nightly"));
+        let reports = vec![
+            JobReport {
+                job: &stable,
+                status: JobStatus::Succeeded,
+                started: None,
+                duration: Duration::from_secs(83),
+                log: Some("stable.log".to_string()),
+            },
+            JobReport {
+                job: &nightly,
+                status: JobStatus::FailedAllowed,
+                started: None,
+                duration: Duration::from_secs(2),
+                log: None,
+            },
+        ];
+        assert_eq!(
+            format_summary(&reports),
+            "OS       VERSION  LANG  VARIANT  STATUS            TIME   LOG\n\
+             FreeBSD  12.0     rust  stable   succeeded         1m23s  stable.log\n\
+             FreeBSD  12.0     rust  nightly  failed (allowed)  0m02s  -\n"
+        );
+        assert!(check_reports(&reports).is_ok());
+    }
+    #[test]
+    fn test_check_reports_failed() {
+        let stable = job("stable");
+        let reports = vec![
+            JobReport {
+                job: &stable,
+                status: JobStatus::Failed,
+                started: None,
+                duration: Duration::default(),
+                log: None,
+            },
+            JobReport {
+                job: &stable,
+                status: JobStatus::FailedAllowed,
+                started: None,
+                duration: Duration::default(),
+                log: None,
+            },
+            JobReport {
+                job: &stable,
+                status: JobStatus::Skipped,
+                started: None,
+                duration: Duration::default(),
+                log: None,
+            },
+        ];
+        assert!(check_reports(&reports).is_err());
+        assert!(check_reports(&reports[1..]).is_ok());
+        let reports = vec![JobReport {
+            job: &stable,
+            status: JobStatus::TimedOut,
+            started: None,
+            duration: Duration::default(),
+            log: None,
+        }];
+        assert!(check_reports(&reports).is_err());
+    }
+    #[test]
+    fn test_get_artifact_path() {
+        let pot_path = std::env::temp_dir().join("freebsd-ci-test-artifact");
+        let artifacts = pot_path.join("m/root/artifacts");
+        std::fs::create_dir_all(&artifacts).unwrap();
+        let missing = get_artifact_path(&pot_path, "missing.tar.gz");
+        std::fs::write(artifacts.join("ci-test.tar.gz"), b"").unwrap();
+        let found = get_artifact_path(&pot_path, "ci-test.tar.gz");
+        std::fs::remove_dir_all(&pot_path).unwrap();
+        assert_eq!(found.unwrap(), artifacts.join("ci-test.tar.gz"));
+        match missing.unwrap_err().downcast::<BuildError>() {
+            Ok(BuildError::ArtifactNotFound { path }) => {
+                assert_eq!(path, artifacts.join("missing.tar.gz").display().to_string())
+            }
+            e => panic!("unexpected error {:?}", e),
+        }
+    }
+    #[test]
+    fn test_deploy_artifact_not_deployed() {
+        let prj = Project {
+            owner: "pizzamig".to_string(),
+            project: "ci-test".to_string(),
+        };
+        let gh = Github::default();
+        // no release: nothing is looked up in the pot
+        let b = job("stable");
+        assert!(deploy_artifact(&b, "no-such-pot", &prj, &BuildOpt::default(), &gh).is_ok());
+        let build_opt = BuildOpt {
+            release_id: Some(1),
+            ..BuildOpt::default()
+        };
+        let b = BuildJob {
+            deploy: false,
+            ..job("stable")
+        };
+        assert!(deploy_artifact(&b, "no-such-pot", &prj, &build_opt, &gh).is_ok());
+    }
+    #[test]
+    fn test_render_build_script_upload() {
+        let prj = Project {
+            owner: "pizzamig".to_string(),
+            project: "ci-test".to_string(),
+        };
+        let build_opt = BuildOpt {
+            release_id: Some(1),
+            ..BuildOpt::default()
+        };
+        let b = job("stable");
+        let script =
+            render_build_script(&b, &prj, &build_opt, &get_build_template(None, &b)).unwrap();
+        // the tarball is left in the pot, freebsd-ci uploads it
+        assert!(script.contains("/root/artifacts/"));
+        assert!(!script.contains("curl"));
+        assert!(!script.contains("api.github.com"));
+    }
+    #[test]
+    fn test_build_pot_error_keep_going() {
+        let prj = Project {
+            owner: "pizzamig".to_string(),
+            project: "ci-test".to_string(),
+        };
+        let opt = Opt::from_iter(&["freebsd-ci", "-k"]);
+        let missing = |variant| BuildJob {
+            os: BuildOS {
+                os_family: "FreeBSD".to_string(),
+                os_version: "0.0".to_string(),
+            },
+            ..job(variant)
+        };
+        let queue = vec![missing("stable"), missing("nightly")];
+        let (reports, error) = build(&queue, &prj, &opt, &BuildOpt::default(), &Github::default());
+        // a pot error stops the build, even with the keep-going flag
+        let status: Vec<JobStatus> = reports.iter().map(|r| r.status).collect();
+        assert_eq!(status, vec![JobStatus::Error, JobStatus::Skipped]);
+        match error.unwrap().downcast::<BuildError>() {
+            Ok(BuildError::PotNotPresent { potname }) => {
+                assert_eq!(potname, "FreeBSD-0_0-rust-stable")
+            }
+            e => panic!("unexpected error {:?}", e),
+        }
+    }
+    #[test]
+    fn test_get_commit_status() {
+        let minute = Duration::from_secs(60);
+        assert_eq!(
+            get_commit_status(JobStatus::Succeeded, minute, Some("done")),
+            (CommitState::Success, "succeeded in 1m00s".to_string())
+        );
+        assert_eq!(
+            get_commit_status(JobStatus::FailedAllowed, minute, Some("error")),
+            (
+                CommitState::Success,
+                "failed (allowed) in 1m00s".to_string()
+            )
+        );
+        assert_eq!(
+            get_commit_status(JobStatus::Stalled, minute, Some("waiting")),
+            (
+                CommitState::Failure,
+                "STALLED in 1m00s: waiting".to_string()
+            )
+        );
+        assert_eq!(
+            get_commit_status(JobStatus::Failed, minute, None),
+            (CommitState::Failure, "FAILED in 1m00s".to_string())
+        );
     }
-    // destroy the fscomp
-    destroy_fscomp(&fscomp_name)?;
-    Ok(())
 }
@@ -0,0 +1,83 @@
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::PathBuf;
+
+pub(crate) fn get_changelog_path(repopath: &str) -> PathBuf {
+    let mut changelog = PathBuf::new();
+    changelog.push(repopath);
+    changelog.push("CHANGELOG.md");
+    changelog
+}
+
+pub(crate) fn get_changelog(repopath: &str) -> Result<String, std::io::Error> {
+    let mut rc = String::new();
+    File::open(get_changelog_path(repopath))?.read_to_string(&mut rc)?;
+    Ok(rc)
+}
+
+/// The version of a release heading, like `## [0.1.1] 2019-02-01`
+fn get_heading_version(line: &str) -> Option<&str> {
+    let heading = line.strip_prefix("## ")?.trim_start();
+    let version = heading.split_whitespace().next()?;
+    Some(version.trim_start_matches('[').trim_end_matches(']'))
+}
+
+/// The section of a Keep-a-Changelog file of the release of the tag, without
+/// its heading; a `v` in front of the tag is ignored
+pub(crate) fn get_release_notes(changelog: &str, tag: &str) -> Option<String> {
+    let version = tag.strip_prefix('v').unwrap_or(tag);
+    let mut lines = changelog.lines().skip_while(|l| {
+        get_heading_version(l)
+            .filter(|v| *v == tag || *v == version)
+            .is_none()
+    });
+    lines.next()?;
+    let section: Vec<&str> = lines
+        .take_while(|l| !l.starts_with("## "))
+        // the link references, at the end of the file
+        .filter(|l| !(l.starts_with('[') && l.contains("]: ")))
+        .collect();
+    let notes = section.join("\n").trim().to_string();
+    Some(notes).filter(|n| !n.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHANGELOG: &str = "# Changelog
+All notable changes to this project will be documented in this file.
+
+## [Unreleased]
+### Added
+- a new feature
+
+## [0.1.1] 2019-02-01
+### Fixed
+- -b option wasn't honored
+
+## 0.1.0 - 2019-01-15
+### Added
+- first release
+
+[0.1.1]: https://github.com/pizzamig/freebsd-ci/compare/0.1.0...0.1.1
+";
+
+    #[test]
+    fn test_get_release_notes() {
+        assert_eq!(
+            get_release_notes(CHANGELOG, "0.1.1"),
+            Some("### Fixed\n- -b option wasn't honored".to_string())
+        );
+        assert_eq!(
+            get_release_notes(CHANGELOG, "v0.1.0"),
+            Some("### Added\n- first release".to_string())
+        );
+        assert_eq!(
+            get_release_notes(CHANGELOG, "Unreleased"),
+            Some("### Added\n- a new feature".to_string())
+        );
+        assert_eq!(get_release_notes(CHANGELOG, "0.2.0"), None);
+        assert_eq!(get_release_notes(CHANGELOG, "0.1"), None);
+    }
+}
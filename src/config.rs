@@ -1,4 +1,5 @@
 use failure::Error;
+use serde::de::{self, Deserializer};
 use serde_derive::Deserialize;
 use std::fs::File;
 use std::io::prelude::*;
@@ -8,6 +9,85 @@ use std::path::Path;
 #[derive(Debug, Deserialize)]
 pub(crate) struct Config {
     pub(crate) tokens: Tokens,
+    #[serde(default)]
+    pub(crate) github: GithubUrls,
+}
+
+/// The github endpoints, to be changed for github enterprise or a local
+/// stand-in server; the ones not set are derived from `api`, so the token is
+/// never sent to a host the configuration didn't ask for
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct GithubUrls {
+    /// The base url of the REST API
+    pub(crate) api: String,
+    pub(crate) graphql: String,
+    /// The base url of the uploads of the release assets
+    pub(crate) uploads: String,
+}
+
+impl Default for GithubUrls {
+    fn default() -> Self {
+        GithubUrls {
+            api: "https://api.github.com".to_string(),
+            graphql: "https://api.github.com/graphql".to_string(),
+            uploads: "https://uploads.github.com".to_string(),
+        }
+    }
+}
+
+/// The `[github]` section as written in the configuration file
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawGithubUrls {
+    api: Option<String>,
+    graphql: Option<String>,
+    uploads: Option<String>,
+}
+
+impl GithubUrls {
+    /// The endpoints of the server of the REST API `api`: a github enterprise
+    /// API (`https://host/api/v3`) has them in `/api`, the others in `api`
+    /// itself
+    fn from_api(api: &str) -> Self {
+        let api = api.trim_end_matches('/');
+        if api == "https://api.github.com" {
+            return GithubUrls::default();
+        }
+        let (graphql, uploads) = if api.ends_with("/api/v3") {
+            let base = &api[..api.len() - "/v3".len()];
+            (format!("{}/graphql", base), format!("{}/uploads", base))
+        } else {
+            (format!("{}/graphql", api), api.to_string())
+        };
+        GithubUrls {
+            api: api.to_string(),
+            graphql,
+            uploads,
+        }
+    }
+}
+
+impl<'de> de::Deserialize<'de> for GithubUrls {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let raw = RawGithubUrls::deserialize(deserializer)?;
+        let api = match raw.api {
+            Some(api) => api,
+            None if raw.graphql.is_none() && raw.uploads.is_none() => {
+                return Ok(GithubUrls::default());
+            }
+            None => {
+                return Err(de::Error::custom(
+                    "the api url is needed when the graphql or the uploads url is set",
+                ));
+            }
+        };
+        let derived = GithubUrls::from_api(&api);
+        Ok(GithubUrls {
+            api: derived.api,
+            graphql: raw.graphql.unwrap_or(derived.graphql),
+            uploads: raw.uploads.unwrap_or(derived.uploads),
+        })
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -23,3 +103,43 @@ pub(crate) fn get_config(path: &Path) -> Result<Config, Error> {
     let config: Config = toml::from_slice(&buf)?;
     Ok(config)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_github_urls() {
+        let config: Config = toml::from_str("[tokens]\ngithub = \"xxx\"\n").unwrap();
+        assert_eq!(config.github, GithubUrls::default());
+        let config: Config = toml::from_str(
+            "[tokens]\ngithub = \"xxx\"\n[github]\napi = \"https://github.example.com/api/v3\"\n",
+        )
+        .unwrap();
+        assert_eq!(config.github.api, "https://github.example.com/api/v3");
+        assert_eq!(
+            config.github.graphql,
+            "https://github.example.com/api/graphql"
+        );
+        assert_eq!(
+            config.github.uploads,
+            "https://github.example.com/api/uploads"
+        );
+        let config: Config = toml::from_str(
+            "[tokens]\ngithub = \"xxx\"\n[github]\napi = \"http://localhost:8080/\"\nuploads = \"http://localhost:8081\"\n",
+        )
+        .unwrap();
+        assert_eq!(config.github.api, "http://localhost:8080");
+        assert_eq!(config.github.graphql, "http://localhost:8080/graphql");
+        assert_eq!(config.github.uploads, "http://localhost:8081");
+        let config: Config = toml::from_str(
+            "[tokens]\ngithub = \"xxx\"\n[github]\napi = \"https://api.github.com\"\n",
+        )
+        .unwrap();
+        assert_eq!(config.github, GithubUrls::default());
+        assert!(toml::from_str::<Config>(
+            "[tokens]\ngithub = \"xxx\"\n[github]\nuploads = \"https://github.example.com/api/uploads\"\n",
+        )
+        .is_err());
+    }
+}
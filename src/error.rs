@@ -1,5 +1,6 @@
 use derive_is_enum_variant::is_enum_variant;
 use failure::Fail;
+use std::fmt::Display;
 
 #[derive(Debug, Fail, is_enum_variant)]
 pub(crate) enum ParseError {
@@ -9,7 +10,74 @@ pub(crate) enum ParseError {
     #[fail(display = "Missing key from the YAML: {}", key)]
     #[is_enum_variant(name = "is_missingkey")]
     MissingKey { key: String },
+    #[fail(display = "Unknown key in the YAML: {}", key)]
+    #[is_enum_variant(name = "is_unknownkey")]
+    UnknownKey { key: String },
+    #[fail(
+        display = "Unsupported schema_version {} (supported up to {})",
+        version, supported
+    )]
+    #[is_enum_variant(name = "is_unsupportedschema")]
+    UnsupportedSchema { version: u64, supported: u32 },
+    #[fail(display = "Unsupported language {}", lang)]
+    #[is_enum_variant(name = "is_unsupportedlanguage")]
+    UnsupportedLanguage { lang: String },
+    #[fail(display = "Unsupported variant {} for the language {}", variant, lang)]
+    #[is_enum_variant(name = "is_unsupportedvariant")]
+    UnsupportedVariant { lang: String, variant: String },
+    #[fail(
+        display = "Unsupported build system {} for the language {}",
+        build_system, lang
+    )]
+    #[is_enum_variant(name = "is_unsupportedbuildsystem")]
+    UnsupportedBuildSystem { lang: String, build_system: String },
     #[fail(display = "Generic Error: {}", msg)]
     #[is_enum_variant(name = "is_genericerror")]
     GenericError { msg: String },
 }
+
+/// An error in a `.bsd-ci.yml` file, with its position and a hint to fix it
+#[derive(Debug)]
+pub(crate) struct YamlDiagnostic {
+    pub(crate) file: String,
+    pub(crate) location: Option<(usize, usize)>,
+    pub(crate) value: Option<String>,
+    pub(crate) hint: Option<String>,
+    pub(crate) error: ParseError,
+}
+
+impl Display for YamlDiagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.location {
+            Some((line, column)) => write!(f, "{}:{}:{}: {}", self.file, line, column, self.error)?,
+            None => write!(f, "{}: {}", self.file, self.error)?,
+        }
+        if let Some(value) = &self.value {
+            write!(f, "\n  value: {}", value)?;
+        }
+        if let Some(hint) = &self.hint {
+            write!(f, "\n  hint: {}", hint)?;
+        }
+        Ok(())
+    }
+}
+
+impl Fail for YamlDiagnostic {}
+
+/// All the errors found in a `.bsd-ci.yml` file
+#[derive(Debug)]
+pub(crate) struct YamlDiagnostics(pub(crate) Vec<YamlDiagnostic>);
+
+impl Display for YamlDiagnostics {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for (i, d) in self.0.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", d)?;
+        }
+        Ok(())
+    }
+}
+
+impl Fail for YamlDiagnostics {}
@@ -1,10 +1,14 @@
+use crate::config::GithubUrls;
 use crate::Project;
 use failure::Error;
+use failure::Fail;
 use log::debug;
 use log::trace;
 use reqwest::header;
-use serde_derive::Deserialize;
+use serde_derive::{Deserialize, Serialize};
 use std::fmt::Display;
+use std::fs::File;
+use std::path::Path;
 
 #[derive(Debug, Deserialize)]
 struct ReplyData {
@@ -60,10 +64,70 @@ pub(crate) struct AssetJson {
 }
 
 #[derive(Debug, Deserialize)]
-struct ReleaseJson {
-    id: u64,
-    tag_name: String,
-    assets: Vec<AssetJson>,
+pub(crate) struct ReleaseJson {
+    pub(crate) id: u64,
+    pub(crate) tag_name: String,
+    /// The description of the release, null if never set
+    #[serde(default)]
+    pub(crate) body: Option<String>,
+    pub(crate) assets: Vec<AssetJson>,
+}
+
+#[derive(Debug, Fail)]
+pub(crate) enum GithubError {
+    #[fail(display = "Github request failed with status {}: {}", status, url)]
+    RequestFailed { url: String, status: u16 },
+}
+
+/// The state of a commit status
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum CommitState {
+    Pending,
+    Success,
+    Failure,
+    Error,
+}
+
+#[derive(Debug, Serialize)]
+struct CommitStatusJson<'a> {
+    state: CommitState,
+    description: &'a str,
+    context: &'a str,
+}
+
+#[derive(Debug, Serialize)]
+struct NewReleaseJson<'a> {
+    tag_name: &'a str,
+    name: &'a str,
+    body: &'a str,
+    draft: bool,
+}
+
+/// The access to github: its endpoints and the token
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Github {
+    pub(crate) urls: GithubUrls,
+    pub(crate) token: String,
+}
+
+impl Github {
+    fn api(&self) -> &str {
+        self.urls.api.trim_end_matches('/')
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ReleaseBodyJson<'a> {
+    body: &'a str,
+}
+
+fn get_client(gh: &Github) -> Result<reqwest::Client, Error> {
+    let mut token_str = "token ".to_string();
+    token_str.push_str(&gh.token);
+    let mut h = header::HeaderMap::new();
+    h.insert(reqwest::header::AUTHORIZATION, token_str.parse().unwrap());
+    Ok(reqwest::Client::builder().default_headers(h).build()?)
 }
 
 fn get_req_remaining(h: &header::HeaderMap) -> u32 {
@@ -74,17 +138,13 @@ fn get_req_remaining(h: &header::HeaderMap) -> u32 {
         .unwrap_or(0)
 }
 
-pub(crate) fn get_status(prj: &Project, token: &str) -> Result<(RepoStatus, u32), Error> {
-    let mut token_str = "token ".to_string();
-    token_str.push_str(token);
-    let mut h = header::HeaderMap::new();
-    h.insert(reqwest::header::AUTHORIZATION, token_str.parse().unwrap());
-    let q = format!(r#"{{ "query" : "query {{ repository(owner: \"{}\", name: \"{}\") {{ isPrivate isArchived isLocked updatedAt url }} user(login: \"{}\") {{ email }} }}" }}"#, prj.owner, prj.project, prj.owner);
-    let client = reqwest::Client::builder().default_headers(h).build()?;
-    let mut reply = client
-        .post("https://api.github.com/graphql")
-        .body(q)
-        .send()?;
+pub(crate) fn get_status(prj: &Project, gh: &Github) -> Result<(RepoStatus, u32), Error> {
+    let q = format!(
+        r#"{{ "query" : "query {{ repository(owner: \"{}\", name: \"{}\") {{ isPrivate isArchived isLocked updatedAt url }} user(login: \"{}\") {{ email }} }}" }}"#,
+        prj.owner, prj.project, prj.owner
+    );
+    let client = get_client(gh)?;
+    let mut reply = client.post(gh.urls.graphql.as_str()).body(q).send()?;
     let req_left = get_req_remaining(&reply.headers());
     let json = reply.text()?;
     trace!("output is {:?}", json);
@@ -115,28 +175,214 @@ fn _get_release_id_from_json(_tag: &str, json: &str) -> Result<(u64, Vec<AssetJs
     Ok((rss.id, rss.assets))
 }
 
+/// The release of the tag in a page of the list of the releases, if any
+fn get_release_from_list(tag: &str, releases: Vec<ReleaseJson>) -> Option<ReleaseJson> {
+    releases.into_iter().find(|r| r.tag_name == tag)
+}
+
+/// The number of releases in a page of the list of the releases
+const RELEASES_PER_PAGE: usize = 100;
+
+/// Look for the release of the tag in the list of the releases, that has the
+/// draft releases too
+fn find_release(
+    prj: &Project,
+    tag: &str,
+    gh: &Github,
+) -> Result<Option<(ReleaseJson, u32)>, Error> {
+    let client = get_client(gh)?;
+    for page in 1.. {
+        let url = format!(
+            "{}/repos/{}/{}/releases?per_page={}&page={}",
+            gh.api(),
+            prj.owner,
+            prj.project,
+            RELEASES_PER_PAGE,
+            page
+        );
+        let mut reply = client.get(&url).send()?;
+        let req_left = check_reply(&url, &reply)?;
+        let json = reply.text()?;
+        trace!("output is {:?}", json);
+        let releases: Vec<ReleaseJson> = serde_json::from_str(&json)?;
+        let last_page = releases.len() < RELEASES_PER_PAGE;
+        if let Some(release) = get_release_from_list(tag, releases) {
+            return Ok(Some((release, req_left)));
+        }
+        if last_page {
+            break;
+        }
+    }
+    Ok(None)
+}
+
+/// The release of the tag, if any, with its assets and its description;
+/// github doesn't find the draft releases by tag, so they are looked for in
+/// the list of the releases
 pub(crate) fn get_release_id(
     prj: &Project,
     tag: &str,
-    token: &str,
-) -> Result<(u64, Vec<AssetJson>, u32), Error> {
-    let mut token_str = "token ".to_string();
-    token_str.push_str(token);
-    let mut h = header::HeaderMap::new();
-    h.insert(reqwest::header::AUTHORIZATION, token_str.parse().unwrap());
+    gh: &Github,
+) -> Result<Option<(ReleaseJson, u32)>, Error> {
     let url = format!(
-        "https://api.github.com/repos/{}/{}/releases/tags/{}",
-        prj.owner, prj.project, tag
+        "{}/repos/{}/{}/releases/tags/{}",
+        gh.api(),
+        prj.owner,
+        prj.project,
+        tag
     );
-    let client = reqwest::Client::builder().default_headers(h).build()?;
+    let client = get_client(gh)?;
     let mut reply = client.get(&url).send()?;
-    let req_left = get_req_remaining(&reply.headers());
+    if reply.status() == reqwest::StatusCode::NOT_FOUND {
+        return find_release(prj, tag, gh);
+    }
+    let req_left = check_reply(&url, &reply)?;
+    let json = reply.text()?;
+    trace!("output is {:?}", json);
+    Ok(Some((serde_json::from_str(&json)?, req_left)))
+}
+
+/// Create the release of the tag, as a draft or published
+pub(crate) fn create_release(
+    prj: &Project,
+    tag: &str,
+    title: &str,
+    body: &str,
+    draft: bool,
+    gh: &Github,
+) -> Result<(u64, Vec<AssetJson>, u32), Error> {
+    let url = format!("{}/repos/{}/{}/releases", gh.api(), prj.owner, prj.project);
+    let body = serde_json::to_string(&NewReleaseJson {
+        tag_name: tag,
+        name: title,
+        body,
+        draft,
+    })?;
+    let client = get_client(gh)?;
+    let mut reply = client.post(&url).body(body).send()?;
+    let req_left = check_reply(&url, &reply)?;
     let json = reply.text()?;
     trace!("output is {:?}", json);
     let (release_id, assets) = _get_release_id_from_json(tag, &json)?;
     Ok((release_id, assets, req_left))
 }
 
+/// Set the status `context` of the commit `sha`; the description is
+/// truncated to the 140 characters accepted by github
+pub(crate) fn set_commit_status(
+    prj: &Project,
+    sha: &str,
+    gh: &Github,
+    context: &str,
+    state: CommitState,
+    description: &str,
+) -> Result<u32, Error> {
+    let url = format!(
+        "{}/repos/{}/{}/statuses/{}",
+        gh.api(),
+        prj.owner,
+        prj.project,
+        sha
+    );
+    let description: String = description.chars().take(140).collect();
+    let body = serde_json::to_string(&CommitStatusJson {
+        state,
+        description: &description,
+        context,
+    })?;
+    let client = get_client(gh)?;
+    let reply = client.post(&url).body(body).send()?;
+    check_reply(&url, &reply)
+}
+
+/// Set the description of a release
+pub(crate) fn update_release_body(
+    prj: &Project,
+    release_id: u64,
+    body: &str,
+    gh: &Github,
+) -> Result<u32, Error> {
+    let url = format!(
+        "{}/repos/{}/{}/releases/{}",
+        gh.api(),
+        prj.owner,
+        prj.project,
+        release_id
+    );
+    let body = serde_json::to_string(&ReleaseBodyJson { body })?;
+    let client = get_client(gh)?;
+    let reply = client.patch(&url).body(body).send()?;
+    check_reply(&url, &reply)
+}
+
+/// Delete an asset of a release
+pub(crate) fn delete_asset(prj: &Project, asset_id: u64, gh: &Github) -> Result<u32, Error> {
+    let url = format!(
+        "{}/repos/{}/{}/releases/assets/{}",
+        gh.api(),
+        prj.owner,
+        prj.project,
+        asset_id
+    );
+    let client = get_client(gh)?;
+    let reply = client.delete(&url).send()?;
+    check_reply(&url, &reply)
+}
+
+/// The url to upload an asset of a release, on the uploads endpoint
+fn get_upload_url(
+    prj: &Project,
+    release_id: u64,
+    name: &str,
+    gh: &Github,
+) -> Result<url::Url, Error> {
+    Ok(url::Url::parse_with_params(
+        &format!(
+            "{}/repos/{}/{}/releases/{}/assets",
+            gh.urls.uploads.trim_end_matches('/'),
+            prj.owner,
+            prj.project,
+            release_id
+        ),
+        &[("name", name)],
+    )?)
+}
+
+/// Upload a tarball as an asset of a release, named after the file
+pub(crate) fn upload_asset(
+    prj: &Project,
+    release_id: u64,
+    path: &Path,
+    gh: &Github,
+) -> Result<u32, Error> {
+    let name = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or_default();
+    let url = get_upload_url(prj, release_id, name, gh)?;
+    let client = get_client(gh)?;
+    let reply = client
+        .post(url.as_str())
+        .header(header::CONTENT_TYPE, "application/gzip")
+        .body(File::open(path)?)
+        .send()?;
+    check_reply(url.as_str(), &reply)
+}
+
+/// Fail if the request wasn't successful, otherwise return the number of
+/// requests left
+fn check_reply(url: &str, reply: &reqwest::Response) -> Result<u32, Error> {
+    let req_left = get_req_remaining(reply.headers());
+    debug!("requests left: {:?}", req_left);
+    if !reply.status().is_success() {
+        return Err(Error::from(GithubError::RequestFailed {
+            url: url.to_string(),
+            status: reply.status().as_u16(),
+        }));
+    }
+    Ok(req_left)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -187,4 +433,89 @@ mod tests {
         assert!(id == 15_076_202);
         assert_eq!(assets.len(), 2);
     }
+
+    #[test]
+    fn test_commit_status_json() {
+        let json = serde_json::to_string(&CommitStatusJson {
+            state: CommitState::Pending,
+            description: "Building",
+            context: "freebsd-ci/FreeBSD-12_0-rust-stable",
+        })
+        .unwrap();
+        assert_eq!(
+            json,
+            r#"{"state":"pending","description":"Building","context":"freebsd-ci/FreeBSD-12_0-rust-stable"}"#
+        );
+    }
+    #[test]
+    fn test_new_release_json() {
+        let json = serde_json::to_string(&NewReleaseJson {
+            tag_name: "0.1.1",
+            name: "0.1.1",
+            body: "",
+            draft: true,
+        })
+        .unwrap();
+        assert_eq!(
+            json,
+            r#"{"tag_name":"0.1.1","name":"0.1.1","body":"","draft":true}"#
+        );
+    }
+    #[test]
+    fn test_github_api() {
+        let mut gh = Github::default();
+        assert_eq!(gh.api(), "https://api.github.com");
+        gh.urls.api = "https://github.example.com/api/v3/".to_string();
+        assert_eq!(gh.api(), "https://github.example.com/api/v3");
+    }
+    #[test]
+    fn test_get_upload_url() {
+        let prj = Project {
+            owner: "pizzamig".to_string(),
+            project: "ci-test".to_string(),
+        };
+        let mut gh = Github::default();
+        let url = get_upload_url(&prj, 15076202, "FreeBSD-12.0-ci-test.tar.gz", &gh).unwrap();
+        assert_eq!(
+            url.as_str(),
+            "https://uploads.github.com/repos/pizzamig/ci-test/releases/15076202/assets?name=FreeBSD-12.0-ci-test.tar.gz"
+        );
+        gh.urls.uploads = "https://github.example.com/api/uploads/".to_string();
+        let url = get_upload_url(&prj, 1, "a b&c.tar.gz", &gh).unwrap();
+        assert_eq!(
+            url.as_str(),
+            "https://github.example.com/api/uploads/repos/pizzamig/ci-test/releases/1/assets?name=a+b%26c.tar.gz"
+        );
+    }
+    #[test]
+    fn test_get_release_from_list() {
+        let json = r#"
+        [
+            {
+                "id": 15076203,
+                "tag_name": "0.1.2",
+                "draft": true,
+                "assets": [
+                    { "id": 42, "name": "FreeBSD-12.0-ci-test.tar.gz" }
+                ]
+            },
+            {
+                "id": 15076202,
+                "tag_name": "0.1.1",
+                "draft": false,
+                "body": "Bug fixes",
+                "assets": []
+            }
+        ]"#;
+        let releases = || serde_json::from_str::<Vec<ReleaseJson>>(json).unwrap();
+        let release = get_release_from_list("0.1.2", releases()).unwrap();
+        assert_eq!(release.id, 15076203);
+        assert_eq!(release.assets[0].name, "FreeBSD-12.0-ci-test.tar.gz");
+        assert_eq!(release.body, None);
+        let release = get_release_from_list("0.1.1", releases()).unwrap();
+        assert_eq!(release.id, 15076202);
+        assert_eq!(release.body, Some("Bug fixes".to_string()));
+        assert!(get_release_from_list("0.2.0", releases()).is_none());
+        assert!(get_release_from_list("0.2.0", Vec::new()).is_none());
+    }
 }
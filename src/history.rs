@@ -0,0 +1,294 @@
+use crate::builder::{format_duration, format_table};
+use crate::logs::RunManifest;
+use chrono::DateTime;
+use failure::{Error, Fail};
+use log::warn;
+use std::fs::{File, OpenOptions};
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+#[derive(Debug, Fail)]
+pub(crate) enum HistoryError {
+    #[fail(display = "Run not found in the history: {}", run_id)]
+    RunNotFound { run_id: String },
+    #[fail(
+        display = "Run {} found for more projects ({}): use <owner>/<project>/{} or -U and -P",
+        run_id, projects, run_id
+    )]
+    AmbiguousRun { run_id: String, projects: String },
+}
+
+/// The history store: one run per line, in JSON, in the log root; it's
+/// not affected by the retention of the log directories
+pub(crate) fn get_history_path(log_root: &Path) -> PathBuf {
+    log_root.join("history.jsonl")
+}
+
+/// Record the run in the history store
+pub(crate) fn append_run(log_root: &Path, run: &RunManifest) -> Result<(), Error> {
+    let mut f = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(get_history_path(log_root))?;
+    writeln!(f, "{}", serde_json::to_string(run)?)?;
+    Ok(())
+}
+
+/// All the runs in the history store, the most recent first
+pub(crate) fn read_history(log_root: &Path) -> Result<Vec<RunManifest>, Error> {
+    let path = get_history_path(log_root);
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let mut runs = Vec::new();
+    for (i, line) in BufReader::new(File::open(&path)?).lines().enumerate() {
+        match serde_json::from_str(&line?) {
+            Ok(run) => runs.push(run),
+            Err(e) => warn!("{}:{}: invalid run ignored: {}", path.display(), i + 1, e),
+        }
+    }
+    runs.reverse();
+    Ok(runs)
+}
+
+/// The most recent run with the given id, as `<owner>/<project>/<run id>`
+/// or as the run id alone, if no other project has a run with that id
+pub(crate) fn find_run<'a>(
+    runs: &'a [RunManifest],
+    run_id: &str,
+) -> Result<&'a RunManifest, Error> {
+    let found: Vec<&RunManifest> = runs
+        .iter()
+        .filter(|r| {
+            r.run_id == run_id || format!("{}/{}/{}", r.owner, r.project, r.run_id) == run_id
+        })
+        .collect();
+    let mut projects: Vec<String> = found
+        .iter()
+        .map(|r| format!("{}/{}", r.owner, r.project))
+        .collect();
+    projects.sort();
+    projects.dedup();
+    match (found.first(), projects.len()) {
+        (None, _) => Err(Error::from(HistoryError::RunNotFound {
+            run_id: run_id.to_string(),
+        })),
+        (Some(run), 1) => Ok(run),
+        (Some(_), _) => Err(Error::from(HistoryError::AmbiguousRun {
+            run_id: run_id.to_string(),
+            projects: projects.join(", "),
+        })),
+    }
+}
+
+fn format_time(rfc3339: &str) -> String {
+    match DateTime::parse_from_rfc3339(rfc3339) {
+        Ok(t) => t.format("%Y-%m-%d %H:%M:%S").to_string(),
+        Err(_) => rfc3339.to_string(),
+    }
+}
+
+/// The outcome of the run: an error, the jobs failed or success
+fn get_result(run: &RunManifest) -> String {
+    let failed = run.jobs.iter().filter(|j| j.status.is_failed()).count();
+    if run.error.is_some() {
+        "ERROR".to_string()
+    } else if failed > 0 {
+        format!("{}/{} FAILED", failed, run.jobs.len())
+    } else {
+        "succeeded".to_string()
+    }
+}
+
+/// The table of the runs
+pub(crate) fn format_history(runs: &[RunManifest]) -> String {
+    let rows: Vec<Vec<String>> = runs
+        .iter()
+        .map(|r| {
+            vec![
+                r.run_id.clone(),
+                format!("{}/{}", r.owner, r.project),
+                r.reference.clone().unwrap_or_else(|| "-".to_string()),
+                r.sha
+                    .as_ref()
+                    .map(|s| s.chars().take(8).collect())
+                    .unwrap_or_else(|| "-".to_string()),
+                format_time(&r.started),
+                format_duration(Duration::from_secs(r.duration_secs)),
+                get_result(r),
+            ]
+        })
+        .collect();
+    format_table(
+        &[
+            "RUN", "PROJECT", "REF", "COMMIT", "STARTED", "TIME", "RESULT",
+        ],
+        &rows,
+    )
+}
+
+/// The details of a run, with the table of its jobs
+pub(crate) fn format_run(run: &RunManifest) -> String {
+    let mut rc = format!("Run {} of {}/{}\n", run.run_id, run.owner, run.project);
+    if let Some(reference) = &run.reference {
+        rc.push_str(&format!("Reference: {}\n", reference));
+    }
+    if let Some(sha) = &run.sha {
+        rc.push_str(&format!("Commit: {}\n", sha));
+    }
+    rc.push_str(&format!(
+        "Started: {}, finished: {} ({})\n",
+        format_time(&run.started),
+        format_time(&run.finished),
+        format_duration(Duration::from_secs(run.duration_secs))
+    ));
+    rc.push_str(&format!("Result: {}\n", get_result(run)));
+    if let Some(error) = &run.error {
+        rc.push_str(&format!("Error: {}\n", error));
+    }
+    rc.push_str(&format!("Logs: {}\n", run.log_dir));
+    if run.jobs.is_empty() {
+        return rc;
+    }
+    let rows: Vec<Vec<String>> = run
+        .jobs
+        .iter()
+        .map(|j| {
+            let env: Vec<String> = j.env.iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+            vec![
+                j.os_family.clone(),
+                j.os_version.clone(),
+                j.lang.clone(),
+                j.lang_variant.clone(),
+                if env.is_empty() {
+                    "-".to_string()
+                } else {
+                    env.join(" ")
+                },
+                j.image.clone(),
+                j.status.to_string(),
+                j.started
+                    .as_ref()
+                    .map(|s| format_time(s))
+                    .unwrap_or_else(|| "-".to_string()),
+                format_duration(Duration::from_secs(j.duration_secs)),
+                j.asset.clone().unwrap_or_else(|| "-".to_string()),
+                if j.logs.is_empty() {
+                    "-".to_string()
+                } else {
+                    j.logs.join(" ")
+                },
+            ]
+        })
+        .collect();
+    rc.push('\n');
+    rc.push_str(&format_table(
+        &[
+            "OS", "VERSION", "LANG", "VARIANT", "ENV", "IMAGE", "STATUS", "STARTED", "TIME",
+            "ASSET", "LOGS",
+        ],
+        &rows,
+    ));
+    rc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::JobStatus;
+    use crate::logs::JobManifest;
+    use std::collections::BTreeMap;
+
+    fn run(run_id: &str, status: JobStatus) -> RunManifest {
+        RunManifest {
+            run_id: run_id.to_string(),
+            owner: "pizzamig".to_string(),
+            project: "ci-test".to_string(),
+            reference: Some("refs/heads/master".to_string()),
+            sha: Some("0123456789abcdef0123456789abcdef01234567".to_string()),
+            log_dir: format!("logs/pizzamig/ci-test/{}", run_id),
+            started: "2019-02-01T15:30:12+01:00".to_string(),
+            finished: "2019-02-01T15:33:24+01:00".to_string(),
+            duration_secs: 192,
+            error: None,
+            jobs: vec![JobManifest {
+                job: "FreeBSD-12_0-rust-stable".to_string(),
+                os_family: "FreeBSD".to_string(),
+                os_version: "12.0".to_string(),
+                lang: "rust".to_string(),
+                lang_variant: "stable".to_string(),
+                env: BTreeMap::new(),
+                image: "FreeBSD-12_0-rust-stable".to_string(),
+                status,
+                started: Some("2019-02-01T15:30:13+01:00".to_string()),
+                finished: Some("2019-02-01T15:33:24+01:00".to_string()),
+                duration_secs: 191,
+                logs: vec!["FreeBSD-12_0-rust-stable.log".to_string()],
+                asset: None,
+            }],
+        }
+    }
+
+    #[test]
+    fn test_history_store() {
+        let log_root = std::env::temp_dir().join("freebsd-ci-test-history");
+        std::fs::create_dir_all(&log_root).unwrap();
+        let first = run("20190201-153012", JobStatus::Succeeded);
+        let second = run("20190202-100000", JobStatus::Failed);
+        append_run(&log_root, &first).unwrap();
+        append_run(&log_root, &second).unwrap();
+        let runs = read_history(&log_root);
+        std::fs::remove_dir_all(&log_root).unwrap();
+        let runs = runs.unwrap();
+        assert_eq!(runs, vec![second.clone(), first.clone()]);
+        assert_eq!(find_run(&runs, "20190201-153012").unwrap(), &first);
+        assert!(find_run(&runs, "20190203-000000").is_err());
+    }
+    #[test]
+    fn test_find_run_ambiguous() {
+        let first = run("20190201-153012", JobStatus::Succeeded);
+        let other = RunManifest {
+            project: "other".to_string(),
+            ..run("20190201-153012", JobStatus::Failed)
+        };
+        let runs = vec![other.clone(), first.clone()];
+        let e = find_run(&runs, "20190201-153012").unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "Run 20190201-153012 found for more projects (pizzamig/ci-test, pizzamig/other): \
+             use <owner>/<project>/20190201-153012 or -U and -P"
+        );
+        assert_eq!(
+            find_run(&runs, "pizzamig/ci-test/20190201-153012").unwrap(),
+            &first
+        );
+        assert_eq!(
+            find_run(&runs, "pizzamig/other/20190201-153012").unwrap(),
+            &other
+        );
+    }
+    #[test]
+    fn test_format_history() {
+        let runs = vec![
+            run("20190202-100000", JobStatus::Stalled),
+            run("20190201-153012", JobStatus::FailedAllowed),
+        ];
+        assert_eq!(
+            format_history(&runs),
+            "RUN              PROJECT           REF                COMMIT    STARTED              TIME   RESULT\n\
+             20190202-100000  pizzamig/ci-test  refs/heads/master  01234567  2019-02-01 15:30:12  3m12s  1/1 FAILED\n\
+             20190201-153012  pizzamig/ci-test  refs/heads/master  01234567  2019-02-01 15:30:12  3m12s  succeeded\n"
+        );
+    }
+    #[test]
+    fn test_format_run() {
+        let rc = format_run(&run("20190201-153012", JobStatus::Succeeded));
+        assert!(rc.starts_with("Run 20190201-153012 of pizzamig/ci-test\n"));
+        assert!(rc.contains("Logs: logs/pizzamig/ci-test/20190201-153012\n"));
+        assert!(rc.ends_with(
+            "FreeBSD  12.0     rust  stable   -    FreeBSD-12_0-rust-stable  succeeded  2019-02-01 15:30:13  3m11s  -      FreeBSD-12_0-rust-stable.log\n"
+        ));
+    }
+}
@@ -0,0 +1,36 @@
+use super::{variants_as_is, Language};
+use crate::error::ParseError;
+use crate::BuildLang;
+
+/// C and C++: the variants are the compilers, like `clang` or `gcc12`
+pub(crate) struct C;
+
+fn is_compiler(variant: &str) -> bool {
+    ["clang", "gcc"]
+        .iter()
+        .any(|c| variant.starts_with(c) && variant[c.len()..].chars().all(|x| x.is_ascii_digit()))
+}
+
+impl Language for C {
+    fn name(&self) -> &'static str {
+        "c"
+    }
+
+    fn parse_variants(&self, variants: &[String]) -> Result<Vec<BuildLang>, ParseError> {
+        if let Some(v) = variants.iter().find(|v| !is_compiler(v)) {
+            return Err(ParseError::UnsupportedVariant {
+                lang: self.name().to_string(),
+                variant: v.to_string(),
+            });
+        }
+        Ok(variants_as_is(self.name(), variants))
+    }
+
+    fn default_template(&self) -> &'static str {
+        include_str!("../../templates/c.sh")
+    }
+
+    fn build_systems(&self) -> &'static [&'static str] {
+        &["make", "cmake", "meson"]
+    }
+}
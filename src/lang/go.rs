@@ -0,0 +1,31 @@
+use super::{is_version, variants_as_is, Language};
+use crate::error::ParseError;
+use crate::BuildLang;
+
+/// Go: the variants are the versions, like `1.21`; the module cache is kept
+/// in a fscomp shared by all the builds
+pub(crate) struct Go;
+
+impl Language for Go {
+    fn name(&self) -> &'static str {
+        "go"
+    }
+
+    fn parse_variants(&self, variants: &[String]) -> Result<Vec<BuildLang>, ParseError> {
+        if let Some(v) = variants.iter().find(|v| !is_version(v)) {
+            return Err(ParseError::UnsupportedVariant {
+                lang: self.name().to_string(),
+                variant: v.to_string(),
+            });
+        }
+        Ok(variants_as_is(self.name(), variants))
+    }
+
+    fn default_template(&self) -> &'static str {
+        include_str!("../../templates/go.sh")
+    }
+
+    fn cache_fscomp(&self) -> Option<(&'static str, &'static str)> {
+        Some(("go-modcache", "/var/cache/gomod"))
+    }
+}
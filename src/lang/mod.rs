@@ -0,0 +1,164 @@
+mod c;
+mod go;
+mod python;
+mod rust;
+
+use crate::error::ParseError;
+use crate::{BuildJob, BuildLang};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// A language supported by the `language` key of the `.bsd-ci.yml` file
+///
+/// To support a new language, implement this trait and add it to `LANGUAGES`
+pub(crate) trait Language {
+    /// The name of the language, as written in the `language` key
+    fn name(&self) -> &'static str;
+
+    /// Check the variants listed under the language key
+    fn parse_variants(&self, variants: &[String]) -> Result<Vec<BuildLang>, ParseError>;
+
+    /// The default build template, `templates/<name>.sh`, embedded in the
+    /// executable
+    fn default_template(&self) -> &'static str;
+
+    /// The build systems supported by the default template; the first one is
+    /// the default
+    fn build_systems(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    /// A fscomp kept across the builds, as name and mount point in the pot,
+    /// like a cache of the dependencies
+    fn cache_fscomp(&self) -> Option<(&'static str, &'static str)> {
+        None
+    }
+}
+
+static LANGUAGES: &[&(dyn Language + Sync)] = &[&rust::Rust, &c::C, &go::Go, &python::Python];
+
+/// Look up a supported language by name
+pub(crate) fn get_language(name: &str) -> Option<&'static dyn Language> {
+    LANGUAGES
+        .iter()
+        .find(|l| l.name() == name)
+        .map(|l| *l as &dyn Language)
+}
+
+/// The names of the supported languages
+pub(crate) fn get_language_names() -> Vec<&'static str> {
+    LANGUAGES.iter().map(|l| l.name()).collect()
+}
+
+/// The build template of a job
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum BuildTemplate {
+    /// A template file, given on the command line
+    File(PathBuf),
+    /// The default template of a language, that doesn't depend on the
+    /// current directory
+    Embedded { name: String, source: &'static str },
+}
+
+impl fmt::Display for BuildTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BuildTemplate::File(path) => write!(f, "{}", path.display()),
+            BuildTemplate::Embedded { name, .. } => write!(f, "templates/{}", name),
+        }
+    }
+}
+
+/// The build template of the job: the one given, or the default one of its language
+pub(crate) fn get_build_template(build_template: Option<&Path>, job: &BuildJob) -> BuildTemplate {
+    match (build_template, get_language(&job.lang.lang)) {
+        (Some(t), _) => BuildTemplate::File(t.to_path_buf()),
+        (None, Some(language)) => BuildTemplate::Embedded {
+            name: format!("{}.sh", language.name()),
+            source: language.default_template(),
+        },
+        (None, None) => {
+            BuildTemplate::File(Path::new("./templates").join(format!("{}.sh", job.lang.lang)))
+        }
+    }
+}
+
+/// If the variant is a version number, like `1.32` or `1.12.4`
+fn is_version(variant: &str) -> bool {
+    !variant.is_empty() && variant.split('.').all(|x| x.parse::<u32>().is_ok())
+}
+
+/// The variants as they are, for languages that accept any name
+fn variants_as_is(lang: &str, variants: &[String]) -> Vec<BuildLang> {
+    variants
+        .iter()
+        .map(|v| BuildLang {
+            lang: lang.to_string(),
+            lang_variant: v.clone(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_language() {
+        assert_eq!(get_language("rust").unwrap().name(), "rust");
+        assert!(get_language("cobol").is_none());
+    }
+    #[test]
+    fn test_parse_variants() {
+        let table: &[(&str, &[&str], &[&str])] = &[
+            (
+                "rust",
+                &[
+                    "stable",
+                    "1.32",
+                    "nightly-2019-01-01",
+                    "1.32.0-x86_64-unknown-freebsd",
+                    "nightly-2019-01-01-x86_64-unknown-freebsd",
+                ],
+                &[
+                    "stabel",
+                    "nightly-2019-1-1",
+                    "stable-",
+                    "1.32-x86_64",
+                    "nightly-2019-01-01x",
+                ],
+            ),
+            ("c", &["clang", "gcc12"], &["icc", "gcc-12", "clang++"]),
+            ("go", &["1.21", "1.22"], &["stable"]),
+            ("python", &["3.9", "3.11"], &["py3"]),
+        ];
+        for (name, ok, unsupported) in table {
+            let language = get_language(name).unwrap();
+            let variants: Vec<String> = ok.iter().map(|v| v.to_string()).collect();
+            let bl = language.parse_variants(&variants).unwrap();
+            assert_eq!(bl.len(), ok.len());
+            assert!(bl.iter().all(|x| x.lang == *name));
+            for v in unsupported.iter() {
+                let err = language.parse_variants(&[v.to_string()]).unwrap_err();
+                assert!(err.is_unsupportedvariant(), "{}: {}", name, v);
+            }
+        }
+    }
+    #[test]
+    fn test_get_build_template() {
+        let job = BuildJob::test("rust", "stable");
+        let template = get_build_template(None, &job);
+        assert_eq!(template.to_string(), "templates/rust.sh");
+        assert_eq!(
+            template,
+            BuildTemplate::Embedded {
+                name: "rust.sh".to_string(),
+                source: include_str!("../../templates/rust.sh"),
+            }
+        );
+        assert_eq!(
+            get_build_template(Some(Path::new("my.sh")), &job),
+            BuildTemplate::File(PathBuf::from("my.sh"))
+        );
+    }
+}
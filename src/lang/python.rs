@@ -0,0 +1,27 @@
+use super::{is_version, variants_as_is, Language};
+use crate::error::ParseError;
+use crate::BuildLang;
+
+/// Python: the variants are the versions, like `3.11`, each one built in its
+/// own virtualenv
+pub(crate) struct Python;
+
+impl Language for Python {
+    fn name(&self) -> &'static str {
+        "python"
+    }
+
+    fn parse_variants(&self, variants: &[String]) -> Result<Vec<BuildLang>, ParseError> {
+        if let Some(v) = variants.iter().find(|v| !is_version(v)) {
+            return Err(ParseError::UnsupportedVariant {
+                lang: self.name().to_string(),
+                variant: v.to_string(),
+            });
+        }
+        Ok(variants_as_is(self.name(), variants))
+    }
+
+    fn default_template(&self) -> &'static str {
+        include_str!("../../templates/python.sh")
+    }
+}
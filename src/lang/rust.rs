@@ -0,0 +1,68 @@
+use super::{is_version, variants_as_is, Language};
+use crate::error::ParseError;
+use crate::BuildLang;
+
+/// Rust: the variants are the rustup toolchains, a channel or a version
+pub(crate) struct Rust;
+
+/// If the string is a date, like `2019-01-01`
+fn is_date(s: &str) -> bool {
+    let parts: Vec<&str> = s.split('-').collect();
+    parts.len() == 3
+        && [4, 2, 2]
+            .iter()
+            .zip(&parts)
+            .all(|(len, p)| p.len() == *len && p.chars().all(|c| c.is_ascii_digit()))
+}
+
+/// If the string is a target triple, like `x86_64-unknown-freebsd`
+fn is_target(s: &str) -> bool {
+    let parts: Vec<&str> = s.split('-').collect();
+    parts.len() >= 2
+        && parts[0].starts_with(|c: char| c.is_ascii_alphabetic())
+        && parts
+            .iter()
+            .all(|p| !p.is_empty() && p.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'))
+}
+
+/// If the variant is a rustup toolchain: a channel or a version, optionally
+/// followed by a date and a target, like `nightly-2019-01-01` or
+/// `1.32.0-x86_64-unknown-freebsd`
+fn is_toolchain(variant: &str) -> bool {
+    let (channel, rest) = match variant.find('-') {
+        Some(i) => (&variant[..i], Some(&variant[i + 1..])),
+        None => (variant, None),
+    };
+    if !["stable", "beta", "nightly"].contains(&channel) && !is_version(channel) {
+        return false;
+    }
+    let rest = match rest {
+        Some(rest) => rest,
+        None => return true,
+    };
+    match rest.get(..10).filter(|d| is_date(d)).map(|_| &rest[10..]) {
+        Some("") => true,
+        Some(target) => target.starts_with('-') && is_target(&target[1..]),
+        None => is_target(rest),
+    }
+}
+
+impl Language for Rust {
+    fn name(&self) -> &'static str {
+        "rust"
+    }
+
+    fn parse_variants(&self, variants: &[String]) -> Result<Vec<BuildLang>, ParseError> {
+        if let Some(v) = variants.iter().find(|v| !is_toolchain(v)) {
+            return Err(ParseError::UnsupportedVariant {
+                lang: self.name().to_string(),
+                variant: v.to_string(),
+            });
+        }
+        Ok(variants_as_is(self.name(), variants))
+    }
+
+    fn default_template(&self) -> &'static str {
+        include_str!("../../templates/rust.sh")
+    }
+}
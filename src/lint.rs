@@ -0,0 +1,112 @@
+use crate::builder::render_build_script;
+use crate::error::ParseError;
+use crate::lang::get_build_template;
+use crate::yaml::{check_config, get_build_queue, get_build_system};
+use crate::{BuildOpt, Project};
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::Path;
+
+/// Check a `.bsd-ci.yml` file and render the build template for every job,
+/// without network, pot or root privileges
+///
+/// It returns the list of all the problems found: the jobs of the keys
+/// without errors are checked even if other keys have some
+pub(crate) fn lint(yaml_file: &Path, build_template: Option<&Path>, prj: &Project) -> Vec<String> {
+    let mut problems = Vec::new();
+    let mut yaml_string = String::new();
+    if let Err(e) = File::open(yaml_file).and_then(|mut f| f.read_to_string(&mut yaml_string)) {
+        problems.push(format!("{}: {}", yaml_file.display(), e));
+        return problems;
+    }
+    let (ci_config, diagnostics) = check_config(&yaml_string, &yaml_file.to_string_lossy());
+    problems.extend(diagnostics.iter().map(|d| d.to_string()));
+    let ci_config = match ci_config {
+        Some(c) => c,
+        None => return problems,
+    };
+    let build_queue = match get_build_queue(&ci_config) {
+        Ok(q) => q,
+        // already reported
+        Err(ParseError::UnsupportedLanguage { .. }) => return problems,
+        Err(e) => {
+            problems.push(format!("{}: {}", yaml_file.display(), e));
+            return problems;
+        }
+    };
+    // a sample context, with a release, to render the upload part too
+    let build_opt = BuildOpt {
+        update: ci_config.update,
+        release_id: Some(1),
+        assets: Vec::new(),
+        sha: Some("0123456789abcdef0123456789abcdef01234567".to_string()),
+        build_system: get_build_system(&ci_config),
+        timeout: ci_config.timeout,
+        stall_timeout: ci_config.stall_timeout,
+        ..BuildOpt::default()
+    };
+    for job in &build_queue {
+        let build_template = get_build_template(build_template, job);
+        if let Err(e) = render_build_script(job, prj, &build_opt, &build_template) {
+            problems.push(format!(
+                "{}: job {}: {}",
+                build_template,
+                job.to_string(),
+                e
+            ));
+        }
+    }
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lint_ok() {
+        let prj = Project {
+            owner: "pizzamig".to_string(),
+            project: "freebsd-ci".to_string(),
+        };
+        let problems = lint(Path::new(".bsd-ci.yml"), None, &prj);
+        assert!(problems.is_empty(), "{:?}", problems);
+    }
+    #[test]
+    fn test_lint_all_problems() {
+        let prj = Project {
+            owner: "pizzamig".to_string(),
+            project: "freebsd-ci".to_string(),
+        };
+        let yaml_file = std::env::temp_dir().join("freebsd-ci-test-lint.yml");
+        std::fs::write(
+            &yaml_file,
+            "os: FreeBSD\nFreeBSD:\n  - 11.2\nlanguage: rust\nrust: [stable, stabel]\nupdte: true\n",
+        )
+        .unwrap();
+        let problems = lint(&yaml_file, Some(Path::new("no-such-template.sh")), &prj);
+        assert_eq!(problems.len(), 3, "{:?}", problems);
+        assert!(problems[0].contains(":3:5: Invalid type for the key FreeBSD"));
+        assert!(problems[1].contains(":5:16: Unsupported variant stabel"));
+        assert!(problems[2].contains(":6:1: Unknown key in the YAML: updte"));
+        // the jobs of the valid variants are checked too
+        std::fs::write(
+            &yaml_file,
+            "os: FreeBSD\nFreeBSD: ['12.0']\nlanguage: rust\nrust: [stable, stabel]\nupdte: true\n",
+        )
+        .unwrap();
+        let problems = lint(&yaml_file, Some(Path::new("no-such-template.sh")), &prj);
+        std::fs::remove_file(&yaml_file).unwrap();
+        assert_eq!(problems.len(), 3, "{:?}", problems);
+        assert!(problems[2].starts_with("no-such-template.sh: job FreeBSD-12_0-rust-stable: "));
+    }
+    #[test]
+    fn test_lint_nofile() {
+        let prj = Project {
+            owner: "pizzamig".to_string(),
+            project: "freebsd-ci".to_string(),
+        };
+        let problems = lint(Path::new("no-such-file.yml"), None, &prj);
+        assert_eq!(problems.len(), 1);
+    }
+}
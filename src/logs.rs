@@ -0,0 +1,358 @@
+use crate::builder::{get_image_name, get_log_path, get_tarball_name, JobReport, JobStatus};
+use crate::{BuildOpt, Project};
+use chrono::{DateTime, Duration, FixedOffset, Local};
+use failure::Error;
+use log::debug;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+const MANIFEST: &str = "manifest.json";
+
+/// The description of a run, written in its log directory
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct RunManifest {
+    pub(crate) run_id: String,
+    pub(crate) owner: String,
+    pub(crate) project: String,
+    pub(crate) reference: Option<String>,
+    pub(crate) sha: Option<String>,
+    /// The directory of the log files of the run
+    pub(crate) log_dir: String,
+    /// The start and the end of the run, in RFC 3339 format
+    pub(crate) started: String,
+    pub(crate) finished: String,
+    pub(crate) duration_secs: u64,
+    /// The error that stopped the run, if any, with its causes
+    pub(crate) error: Option<String>,
+    pub(crate) jobs: Vec<JobManifest>,
+}
+
+/// The outcome of a job of a run
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct JobManifest {
+    pub(crate) job: String,
+    pub(crate) os_family: String,
+    pub(crate) os_version: String,
+    pub(crate) lang: String,
+    pub(crate) lang_variant: String,
+    pub(crate) env: BTreeMap<String, String>,
+    pub(crate) image: String,
+    pub(crate) status: JobStatus,
+    /// The start and the end of the job, in RFC 3339 format, if it started
+    pub(crate) started: Option<String>,
+    pub(crate) finished: Option<String>,
+    pub(crate) duration_secs: u64,
+    /// The log files, relative to the log directory of the run
+    pub(crate) logs: Vec<String>,
+    /// The tarball deployed to the release, if any
+    pub(crate) asset: Option<String>,
+}
+
+impl RunManifest {
+    pub(crate) fn new(
+        run_id: &str,
+        prj: &Project,
+        reference: Option<String>,
+        build_opt: &BuildOpt,
+        started: DateTime<Local>,
+        reports: &[JobReport],
+        error: Option<&Error>,
+    ) -> RunManifest {
+        let finished = Local::now();
+        RunManifest {
+            run_id: run_id.to_string(),
+            owner: prj.owner.clone(),
+            project: prj.project.clone(),
+            reference,
+            sha: build_opt.sha.clone(),
+            log_dir: build_opt.log_dir.display().to_string(),
+            started: started.to_rfc3339(),
+            finished: finished.to_rfc3339(),
+            duration_secs: (finished - started).num_seconds().max(0) as u64,
+            error: error.map(|e| {
+                let causes: Vec<String> = e.iter_chain().map(|c| c.to_string()).collect();
+                causes.join(": ")
+            }),
+            jobs: reports
+                .iter()
+                .map(|r| JobManifest::new(r, prj, build_opt))
+                .collect(),
+        }
+    }
+}
+
+impl JobManifest {
+    fn new(r: &JobReport, prj: &Project, build_opt: &BuildOpt) -> JobManifest {
+        let logs = ["", "_err", "_combined"]
+            .iter()
+            .map(|suffix| get_log_path(&build_opt.log_dir, r.job, suffix))
+            .filter(|l| l.exists())
+            .filter_map(|l| Some(l.file_name()?.to_string_lossy().into_owned()))
+            .collect();
+        // the tarball is uploaded after the build
+        let deployed =
+            r.job.deploy && build_opt.release_id.is_some() && r.status == JobStatus::Succeeded;
+        JobManifest {
+            job: r.job.to_string(),
+            os_family: r.job.os.os_family.clone(),
+            os_version: r.job.os.os_version.clone(),
+            lang: r.job.lang.lang.clone(),
+            lang_variant: r.job.lang.lang_variant.clone(),
+            env: r.job.env.clone(),
+            image: get_image_name(r.job),
+            status: r.status,
+            started: r.started.map(|s| s.to_rfc3339()),
+            finished: r.started.map(|s| {
+                (s + Duration::from_std(r.duration).unwrap_or_else(|_| Duration::zero()))
+                    .to_rfc3339()
+            }),
+            duration_secs: r.duration.as_secs(),
+            logs,
+            asset: Some(get_tarball_name(r.job, prj)).filter(|_| deployed),
+        }
+    }
+}
+
+/// The directory with the runs of the project: `<log_root>/<owner>/<project>`
+pub(crate) fn get_project_log_dir(log_root: &Path, prj: &Project) -> PathBuf {
+    log_root.join(&prj.owner).join(&prj.project)
+}
+
+/// Create the log directory of a new run of the project and return its id,
+/// the start time of the run, made unique if needed
+pub(crate) fn create_run_dir(log_root: &Path, prj: &Project) -> Result<(String, PathBuf), Error> {
+    let project_dir = get_project_log_dir(log_root, prj);
+    fs::create_dir_all(&project_dir)?;
+    let base = Local::now().format("%Y%m%d-%H%M%S").to_string();
+    let mut run_id = base.clone();
+    let mut n = 0;
+    loop {
+        let run_dir = project_dir.join(&run_id);
+        match fs::create_dir(&run_dir) {
+            Ok(()) => return Ok((run_id, run_dir)),
+            Err(ref e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                n += 1;
+                run_id = format!("{}-{}", base, n);
+            }
+            Err(e) => return Err(Error::from(e)),
+        }
+    }
+}
+
+pub(crate) fn write_manifest(run_dir: &Path, manifest: &RunManifest) -> Result<(), Error> {
+    let f = File::create(run_dir.join(MANIFEST))?;
+    serde_json::to_writer_pretty(f, manifest)?;
+    Ok(())
+}
+
+pub(crate) fn read_manifest(run_dir: &Path) -> Result<RunManifest, Error> {
+    let f = File::open(run_dir.join(MANIFEST))?;
+    Ok(serde_json::from_reader(f)?)
+}
+
+/// The runs in the project log directory, the most recent first; the
+/// directories without a manifest are ignored
+pub(crate) fn get_runs(project_dir: &Path) -> Result<Vec<(PathBuf, RunManifest)>, Error> {
+    let mut runs = Vec::new();
+    if !project_dir.is_dir() {
+        return Ok(runs);
+    }
+    for entry in fs::read_dir(project_dir)? {
+        let run_dir = entry?.path();
+        if let Ok(manifest) = read_manifest(&run_dir) {
+            runs.push((run_dir, manifest));
+        }
+    }
+    runs.sort_by_key(|(_, m)| std::cmp::Reverse(get_run_order(m)));
+    Ok(runs)
+}
+
+/// The order of the runs: by start time and, for the runs started in the
+/// same second, by the suffix of their id
+fn get_run_order(manifest: &RunManifest) -> (Option<DateTime<FixedOffset>>, u32) {
+    let suffix = manifest
+        .run_id
+        .splitn(3, '-')
+        .nth(2)
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(0);
+    (DateTime::parse_from_rfc3339(&manifest.started).ok(), suffix)
+}
+
+/// Remove the runs of the project beyond the most recent `keep_runs` or
+/// older than `keep_days`, and return their directories
+pub(crate) fn prune_runs(
+    project_dir: &Path,
+    keep_runs: Option<usize>,
+    keep_days: Option<u64>,
+    now: DateTime<Local>,
+) -> Result<Vec<PathBuf>, Error> {
+    let mut removed = Vec::new();
+    for (i, (run_dir, manifest)) in get_runs(project_dir)?.into_iter().enumerate() {
+        let too_many = keep_runs.iter().any(|k| i >= *k);
+        let too_old = match (keep_days, DateTime::parse_from_rfc3339(&manifest.started)) {
+            (Some(days), Ok(started)) => {
+                now.signed_duration_since(started) > Duration::days(days as i64)
+            }
+            _ => false,
+        };
+        if too_many || too_old {
+            debug!("Removing the logs of the run {}", manifest.run_id);
+            fs::remove_dir_all(&run_dir)?;
+            removed.push(run_dir);
+        }
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn project(name: &str) -> Project {
+        Project {
+            owner: "freebsd-ci-test".to_string(),
+            project: name.to_string(),
+        }
+    }
+
+    fn manifest(run_id: &str, started: DateTime<Local>) -> RunManifest {
+        RunManifest::new(
+            run_id,
+            &project("prune"),
+            None,
+            &BuildOpt::default(),
+            started,
+            &[],
+            None,
+        )
+    }
+
+    #[test]
+    fn test_create_run_dir() {
+        let root = std::env::temp_dir().join("freebsd-ci-test-create-run-dir");
+        let prj = project("create");
+        let (first, first_dir) = create_run_dir(&root, &prj).unwrap();
+        let (second, second_dir) = create_run_dir(&root, &prj).unwrap();
+        fs::remove_dir_all(&root).unwrap();
+        assert_ne!(first, second);
+        assert_eq!(
+            first_dir,
+            root.join("freebsd-ci-test").join("create").join(&first)
+        );
+        assert_eq!(second_dir.parent(), first_dir.parent());
+    }
+    #[test]
+    fn test_manifest() {
+        let run_dir = std::env::temp_dir().join("freebsd-ci-test-manifest");
+        fs::create_dir_all(&run_dir).unwrap();
+        let m = manifest("20190201-120000", Local::now());
+        write_manifest(&run_dir, &m).unwrap();
+        let read = read_manifest(&run_dir);
+        fs::remove_dir_all(&run_dir).unwrap();
+        assert_eq!(read.unwrap(), m);
+    }
+    #[test]
+    fn test_manifest_error() {
+        let job = |variant| crate::BuildJob::test("rust", variant);
+        let (stable, nightly) = (job("stable"), job("nightly"));
+        let reports = vec![
+            JobReport {
+                job: &stable,
+                status: JobStatus::Error,
+                started: Some(Local::now()),
+                duration: std::time::Duration::from_secs(3),
+                log: None,
+            },
+            JobReport {
+                job: &nightly,
+                status: JobStatus::Skipped,
+                started: None,
+                duration: std::time::Duration::default(),
+                log: None,
+            },
+        ];
+        let error = Error::from(failure::err_msg("pot start failed").context("Job stopped"));
+        let m = RunManifest::new(
+            "20190201-120000",
+            &project("error"),
+            None,
+            &BuildOpt::default(),
+            Local::now(),
+            &reports,
+            Some(&error),
+        );
+        assert_eq!(m.error, Some("Job stopped: pot start failed".to_string()));
+        let jobs: Vec<(&str, JobStatus)> =
+            m.jobs.iter().map(|j| (j.job.as_str(), j.status)).collect();
+        assert_eq!(
+            jobs,
+            vec![
+                ("FreeBSD-12_0-rust-stable", JobStatus::Error),
+                ("FreeBSD-12_0-rust-nightly", JobStatus::Skipped)
+            ]
+        );
+        assert!(m.jobs[1].started.is_none());
+    }
+    #[test]
+    fn test_prune_runs() {
+        let project_dir = std::env::temp_dir().join("freebsd-ci-test-prune-runs");
+        let now = Local::now();
+        for (run_id, days) in &[
+            ("20190201-120000", 10),
+            ("20190205-120000", 3),
+            ("20190206-120000", 2),
+            ("20190207-120000", 1),
+        ] {
+            let run_dir = project_dir.join(run_id);
+            fs::create_dir_all(&run_dir).unwrap();
+            write_manifest(&run_dir, &manifest(run_id, now - Duration::days(*days))).unwrap();
+        }
+        // not a run
+        fs::create_dir_all(project_dir.join("other")).unwrap();
+        let by_age = prune_runs(&project_dir, None, Some(5), now).unwrap();
+        let by_count = prune_runs(&project_dir, Some(2), None, now).unwrap();
+        let runs: Vec<String> = get_runs(&project_dir)
+            .unwrap()
+            .into_iter()
+            .map(|(_, m)| m.run_id)
+            .collect();
+        let other = project_dir.join("other").is_dir();
+        fs::remove_dir_all(&project_dir).unwrap();
+        assert_eq!(by_age, vec![project_dir.join("20190201-120000")]);
+        assert_eq!(by_count, vec![project_dir.join("20190205-120000")]);
+        assert_eq!(runs, vec!["20190207-120000", "20190206-120000"]);
+        assert!(other);
+    }
+    #[test]
+    fn test_get_runs_order() {
+        let project_dir = std::env::temp_dir().join("freebsd-ci-test-get-runs-order");
+        let now = Local::now();
+        // created in the same second: the suffix orders them
+        for run_id in &["20190201-120000-2", "20190201-120000-10", "20190201-120000"] {
+            let run_dir = project_dir.join(run_id);
+            fs::create_dir_all(&run_dir).unwrap();
+            write_manifest(&run_dir, &manifest(run_id, now)).unwrap();
+        }
+        let runs: Vec<String> = get_runs(&project_dir)
+            .unwrap()
+            .into_iter()
+            .map(|(_, m)| m.run_id)
+            .collect();
+        let pruned = prune_runs(&project_dir, Some(1), None, now).unwrap();
+        fs::remove_dir_all(&project_dir).unwrap();
+        assert_eq!(
+            runs,
+            vec!["20190201-120000-10", "20190201-120000-2", "20190201-120000"]
+        );
+        assert_eq!(
+            pruned,
+            vec![
+                project_dir.join("20190201-120000-2"),
+                project_dir.join("20190201-120000")
+            ]
+        );
+    }
+}
@@ -1,23 +1,31 @@
 mod builder;
+mod changelog;
 mod config;
 mod error;
 mod github;
+mod history;
+mod lang;
+mod lint;
+mod logs;
+mod output;
 mod pot;
 mod yaml;
-use crate::builder::build;
-use crate::error::ParseError;
-use crate::github::{get_release_id, get_status, AssetJson};
-use crate::yaml::{
-    get_build_lang, get_build_os, get_lang, get_no_deploy, get_os, get_update, get_yaml,
+use crate::builder::{build, check_reports, format_summary};
+use crate::changelog::{get_changelog, get_release_notes};
+use crate::github::{
+    create_release, get_release_id, get_status, update_release_body, AssetJson, Github,
 };
+use crate::history::{append_run, find_run, format_history, format_run, read_history};
+use crate::logs::{create_run_dir, get_project_log_dir, prune_runs, write_manifest, RunManifest};
+use crate::yaml::{get_build_queue, get_build_system, get_yaml, get_yaml_path, parse_config};
 use exitfailure::ExitFailure;
 use failure::ResultExt;
-use log::{debug, error, info};
+use log::{debug, error};
+use std::collections::BTreeMap;
 use std::fmt::Display;
 use std::path::PathBuf;
 use std::string::ToString;
 use structopt::StructOpt;
-use yaml_rust::YamlLoader;
 
 #[derive(Debug, StructOpt)]
 struct Opt {
@@ -31,30 +39,177 @@ struct Opt {
         default_value = "./freebsd-ci.conf"
     )]
     configfile: PathBuf,
-    /// The pathname to the build-sh template
-    #[structopt(
-        short = "-b",
-        long = "--build",
-        parse(from_os_str),
-        default_value = "./templates/build.sh"
-    )]
-    build_template: PathBuf,
+    /// The pathname to the build-sh template (default: the template of the language)
+    #[structopt(short = "-b", long = "--build", parse(from_os_str))]
+    build_template: Option<PathBuf>,
     /// A Flag to force operations (i.e. remove fscomp or images with the same name)
     #[structopt(short = "-f", long = "--force")]
     force_flag: bool,
     /// A Flag to rendert the build script only (on stdout)
     #[structopt(short = "-B", long = "--build-script-only")]
     render_build_flag: bool,
-    /// Github project name
+    /// A Flag to build all the jobs, even after a failed one, and report all the failures
+    #[structopt(short = "-k", long = "--keep-going")]
+    keep_going_flag: bool,
+    /// A Flag to report the outcome of every job as a commit status on github
+    #[structopt(long = "--commit-status")]
+    commit_status_flag: bool,
+    /// The number of jobs built at the same time, each one on its own clone of the fscomp
+    #[structopt(short = "-j", long = "--jobs", default_value = "1")]
+    jobs: usize,
+    /// The default timeout of a job, in minutes (the YAML file can override it)
+    #[structopt(long = "--timeout")]
+    timeout: Option<u64>,
+    /// The minutes without any output after which a job is killed (the YAML file can override it)
+    #[structopt(long = "--stall-timeout")]
+    stall_timeout: Option<u64>,
+    /// The root of the log directories, organized as <owner>/<project>/<run id>
+    #[structopt(long = "--log-dir", parse(from_os_str), default_value = "./logs")]
+    log_dir: PathBuf,
+    /// The number of runs of the project whose logs are kept, the current one included (default: all)
+    #[structopt(long = "--keep-runs", parse(try_from_str = "parse_keep_runs"))]
+    keep_runs: Option<usize>,
+    /// The number of days the logs of a run are kept (default: forever)
+    #[structopt(long = "--keep-days")]
+    keep_days: Option<u64>,
+    /// Github project name (mandatory, if no subcommand is used)
     #[structopt(short = "-P", long = "--project")]
-    project_name: String,
-    /// Github user name
+    project_name: Option<String>,
+    /// Github user name (mandatory, if no subcommand is used)
     #[structopt(short = "-U", long = "--user-name")]
-    user_name: String,
+    user_name: Option<String>,
     /// Tag name: Using this option, a tag will be built. If a related release is found,
     /// the artifacts will be uploaded
     #[structopt(short = "-T", long = "--tag-name")]
     tag_name: Option<String>,
+    /// A flag to create the release of the tag, if missing
+    #[structopt(long = "--create-release", raw(requires = r#""tag_name""#))]
+    create_release_flag: bool,
+    /// A flag to create the release as a draft, instead of published
+    #[structopt(long = "--release-draft", raw(requires = r#""create_release_flag""#))]
+    release_draft_flag: bool,
+    /// The title of the release created (default: the tag name)
+    #[structopt(long = "--release-title", raw(requires = r#""create_release_flag""#))]
+    release_title: Option<String>,
+    /// The description of the release created
+    #[structopt(long = "--release-body", raw(requires = r#""create_release_flag""#))]
+    release_body: Option<String>,
+    /// A flag not to use the section of the tag in the CHANGELOG.md of the project as the
+    /// description of the release
+    #[structopt(long = "--no-changelog-notes", raw(requires = r#""tag_name""#))]
+    no_changelog_notes_flag: bool,
+    /// A flag to replace the description of an existing release with the section of the tag
+    /// in the CHANGELOG.md, even if it's not empty
+    #[structopt(
+        long = "--replace-release-notes",
+        raw(
+            requires = r#""tag_name""#,
+            conflicts_with = r#""no_changelog_notes_flag""#
+        )
+    )]
+    replace_release_notes_flag: bool,
+    /// Git reference to build: a branch, a tag or a full reference (i.e. refs/heads/main)
+    #[structopt(
+        short = "-R",
+        long = "--ref",
+        raw(conflicts_with_all = r#"&["tag_name", "commit"]"#)
+    )]
+    git_ref: Option<String>,
+    /// Commit SHA to build
+    #[structopt(long = "--commit", raw(conflicts_with = r#""tag_name""#))]
+    commit: Option<String>,
+    /// Pull request number to build: the jobs are built, but nothing is deployed
+    #[structopt(
+        long = "--pull-request",
+        raw(conflicts_with_all = r#"&["git_ref", "commit"]"#)
+    )]
+    pull_request: Option<u64>,
+    /// A flag to build the merge of the pull request, instead of its head
+    #[structopt(long = "--merge", raw(requires = r#""pull_request""#))]
+    merge_flag: bool,
+    /// Local source: a directory, copied as it is (uncommitted changes included),
+    /// or a file:// git url, cloned. No github access is performed
+    #[structopt(short = "-S", long = "--source", parse(try_from_str = "parse_source"))]
+    source: Option<Source>,
+    #[structopt(subcommand)]
+    cmd: Option<Cmd>,
+}
+
+/// A local source of the project, used instead of github
+#[derive(Debug, Clone, PartialEq)]
+enum Source {
+    Path(PathBuf),
+    Url(url::Url),
+}
+
+impl Source {
+    /// The project name, from the last component of the path or of the url
+    fn project_name(&self) -> Option<String> {
+        let name = match self {
+            Source::Path(p) => p.canonicalize().ok()?.file_name()?.to_str()?.to_string(),
+            Source::Url(u) => u.path_segments()?.rfind(|x| !x.is_empty())?.to_string(),
+        };
+        Some(name.trim_end_matches(".git").to_string())
+    }
+}
+
+/// The reference of a pull request: its head or its merge in the base branch
+fn pull_request_ref(number: u64, merge: bool) -> String {
+    format!(
+        "refs/pull/{}/{}",
+        number,
+        if merge { "merge" } else { "head" }
+    )
+}
+
+/// The number of runs kept: at least one, the current run
+fn parse_keep_runs(s: &str) -> Result<usize, String> {
+    match s.parse::<usize>() {
+        Ok(0) => Err("at least 1 run has to be kept".to_string()),
+        Ok(n) => Ok(n),
+        Err(e) => Err(format!("{}: {}", s, e)),
+    }
+}
+
+fn parse_source(s: &str) -> Result<Source, String> {
+    if s.contains("://") {
+        let u = url::Url::parse(s).map_err(|e| format!("{}: {}", s, e))?;
+        if u.scheme() != "file" {
+            return Err(format!("{}: only file:// urls are supported", s));
+        }
+        Ok(Source::Url(u))
+    } else {
+        let p = PathBuf::from(s);
+        if !p.is_dir() {
+            return Err(format!("{}: not a directory", s));
+        }
+        Ok(Source::Path(p))
+    }
+}
+
+#[derive(Debug, StructOpt)]
+enum Cmd {
+    /// Validate a .bsd-ci.yml file and render the build template for every job, offline
+    #[structopt(name = "lint")]
+    Lint {
+        /// The pathname to the .bsd-ci.yml file
+        #[structopt(parse(from_os_str), default_value = "./.bsd-ci.yml")]
+        yaml_file: PathBuf,
+    },
+    /// List the runs in the history, the most recent first (filtered by -U and -P, if any)
+    #[structopt(name = "history")]
+    History {
+        /// The number of runs listed
+        #[structopt(short = "-n", long = "--last", default_value = "20")]
+        last: usize,
+    },
+    /// Show a run of the history, with all its jobs
+    #[structopt(name = "show")]
+    Show {
+        /// The id of the run, as listed by history, as <owner>/<project>/<run id> if other
+        /// projects have a run with the same id
+        run_id: String,
+    },
 }
 
 #[derive(Debug, Clone)]
@@ -90,14 +245,23 @@ struct BuildJob {
     lang: BuildLang,
     os: BuildOS,
     deploy: bool,
+    allow_failure: bool,
+    /// The timeout of the job in minutes, if different from the default one
+    timeout: Option<u64>,
+    env: BTreeMap<String, String>,
 }
 
 fn print_jobs(jv: &[BuildJob]) {
     println!("\nJob queue of {} elements", jv.len());
     for j in jv {
         println!(
-            "BuildJob: {} {}  {} ({}) deploy: {}",
-            j.os.os_family, j.os.os_version, j.lang.lang, j.lang.lang_variant, j.deploy
+            "BuildJob: {} {}  {} ({}) deploy: {} allow_failure: {}",
+            j.os.os_family,
+            j.os.os_version,
+            j.lang.lang,
+            j.lang.lang_variant,
+            j.deploy,
+            j.allow_failure
         );
     }
 }
@@ -115,6 +279,27 @@ impl ToString for BuildJob {
     }
 }
 
+#[cfg(test)]
+impl BuildJob {
+    /// A deployed job on FreeBSD 12.0, without timeout and environment
+    fn test(lang: &str, variant: &str) -> BuildJob {
+        BuildJob {
+            os: BuildOS {
+                os_family: "FreeBSD".to_string(),
+                os_version: "12.0".to_string(),
+            },
+            lang: BuildLang {
+                lang: lang.to_string(),
+                lang_variant: variant.to_string(),
+            },
+            deploy: true,
+            allow_failure: false,
+            timeout: None,
+            env: BTreeMap::new(),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub(crate) struct Project {
     pub(crate) owner: String,
@@ -132,99 +317,336 @@ pub(crate) struct BuildOpt {
     pub(crate) update: bool,
     pub(crate) release_id: Option<u64>,
     pub(crate) assets: Vec<AssetJson>,
+    pub(crate) sha: Option<String>,
+    pub(crate) build_system: Option<String>,
+    pub(crate) timeout: Option<u64>,
+    pub(crate) stall_timeout: Option<u64>,
+    /// The directory of the log files of the run
+    pub(crate) log_dir: PathBuf,
+    /// The commit the outcome of the jobs is reported on, as commit statuses
+    /// on github, if requested
+    pub(crate) status_sha: Option<String>,
+}
+
+/// The release notes of the tag, from the CHANGELOG.md of the project
+fn get_changelog_notes(path: &str, tag_name: &str) -> Option<String> {
+    let changelog = match get_changelog(path) {
+        Ok(changelog) => changelog,
+        Err(e) => {
+            println!("No release notes: CHANGELOG.md not readable: {}", e);
+            return None;
+        }
+    };
+    let notes = get_release_notes(&changelog, tag_name);
+    if notes.is_none() {
+        println!(
+            "No release notes: no section for {} in CHANGELOG.md",
+            tag_name
+        );
+    }
+    notes
+}
+
+/// If the description of an existing release is replaced by the release notes:
+/// only if it's empty, unless requested
+fn is_release_body_replaced(body: Option<&str>, replace: bool) -> bool {
+    replace || body.is_none_or(|b| b.trim().is_empty())
+}
+
+/// The release of the tag, with its assets; if missing, it's created, if
+/// requested; the release notes, if any, are the description of the release
+/// created and of the existing one without a description
+fn get_release(
+    prj: &Project,
+    tag_name: &str,
+    notes: Option<&str>,
+    opt: &Opt,
+    gh: &Github,
+) -> Result<Option<(u64, Vec<AssetJson>)>, failure::Error> {
+    let release = get_release_id(prj, tag_name, gh)
+        .with_context(|_| format!("Fetch release data failed for the tag {}", tag_name))?;
+    if let Some((release, _)) = release {
+        match notes {
+            Some(notes) if release.body.as_deref() == Some(notes) => {}
+            Some(notes)
+                if is_release_body_replaced(
+                    release.body.as_deref(),
+                    opt.replace_release_notes_flag,
+                ) =>
+            {
+                update_release_body(prj, release.id, notes, gh).with_context(|_| {
+                    format!("Failed to update the release notes of the tag {}", tag_name)
+                })?;
+                println!("Updated the release notes of the tag {}", tag_name);
+            }
+            Some(_) => println!(
+                "Kept the description of the release of the tag {} (--replace-release-notes to replace it)",
+                tag_name
+            ),
+            None => {}
+        }
+        return Ok(Some((release.id, release.assets)));
+    }
+    if !opt.create_release_flag {
+        println!(
+            "No release found for the tag {}: nothing will be deployed (--create-release to create it)",
+            tag_name
+        );
+        return Ok(None);
+    }
+    let title = opt.release_title.as_deref().unwrap_or(tag_name);
+    let body = opt.release_body.as_deref().or(notes).unwrap_or_default();
+    let (release_id, assets, _) =
+        create_release(prj, tag_name, title, body, opt.release_draft_flag, gh)
+            .with_context(|_| format!("Failed to create the release of the tag {}", tag_name))?;
+    println!(
+        "Created the {}release {} of the tag {}",
+        if opt.release_draft_flag { "draft " } else { "" },
+        title,
+        tag_name
+    );
+    Ok(Some((release_id, assets)))
 }
 
 fn main() -> Result<(), ExitFailure> {
     let opt = Opt::from_args();
     env_logger::try_init()?;
     debug!("BSD Continuous integration");
+    if let Some(Cmd::Lint { yaml_file }) = &opt.cmd {
+        let prj = Project {
+            owner: opt.user_name.clone().unwrap_or_else(|| "user".to_string()),
+            project: opt
+                .project_name
+                .clone()
+                .unwrap_or_else(|| "project".to_string()),
+        };
+        let problems = crate::lint::lint(yaml_file, opt.build_template.as_deref(), &prj);
+        for p in &problems {
+            eprintln!("{}\n", p);
+        }
+        if !problems.is_empty() {
+            return Err(ExitFailure::from(failure::err_msg(format!(
+                "{} problem(s) found",
+                problems.len()
+            ))));
+        }
+        println!("{}: no problems found", yaml_file.display());
+        return Ok(());
+    }
+    if let Some(Cmd::History { .. }) | Some(Cmd::Show { .. }) = &opt.cmd {
+        let mut runs = read_history(&opt.log_dir)?;
+        runs.retain(|r| {
+            opt.user_name.iter().all(|u| *u == r.owner)
+                && opt.project_name.iter().all(|p| *p == r.project)
+        });
+        match &opt.cmd {
+            Some(Cmd::History { last }) => {
+                runs.truncate(*last);
+                print!("{}", format_history(&runs));
+            }
+            Some(Cmd::Show { run_id }) => print!("{}", format_run(find_run(&runs, run_id)?)),
+            _ => {}
+        }
+        return Ok(());
+    }
+    let prj = match (&opt.user_name, &opt.project_name, &opt.source) {
+        (Some(owner), Some(project), _) => Project {
+            owner: owner.clone(),
+            project: project.clone(),
+        },
+        (_, _, Some(source)) => Project {
+            owner: opt.user_name.clone().unwrap_or_else(|| "local".to_string()),
+            project: opt
+                .project_name
+                .clone()
+                .or_else(|| source.project_name())
+                .unwrap_or_else(|| "project".to_string()),
+        },
+        _ => structopt::clap::Error::with_description(
+            "--user-name and --project are required to build a project",
+            structopt::clap::ErrorKind::MissingRequiredArgument,
+        )
+        .exit(),
+    };
+    let rev = match opt.pull_request {
+        Some(n) => Some(pull_request_ref(n, opt.merge_flag)),
+        None => opt.git_ref.clone().or_else(|| opt.commit.clone()),
+    };
+    if let (Some(Source::Path(_)), true) = (&opt.source, rev.is_some() || opt.tag_name.is_some()) {
+        structopt::clap::Error::with_description(
+            "--ref, --commit, --pull-request and --tag-name cannot be used with a local directory",
+            structopt::clap::ErrorKind::ArgumentConflict,
+        )
+        .exit();
+    }
     /* Initial checks */
     if !crate::pot::is_pot_available() {
         error!("This error needs better explanation");
         return Ok(());
     }
-    debug!(
-        "Reading configuration file {}",
-        opt.configfile
-            .to_str()
-            .unwrap_or("Filename not convertible")
-    );
-    let config = crate::config::get_config(&opt.configfile).with_context(|_| {
-        format!(
-            "could not parse the file {}",
-            opt.configfile.to_str().unwrap_or("file name not printable")
-        )
-    })?;
-    let prj = Project {
-        owner: opt.user_name.clone(),
-        project: opt.project_name.clone(),
+    // every run is recorded, even if it stops before building any job
+    let run = if opt.render_build_flag {
+        None
+    } else {
+        let (run_id, run_dir) = create_run_dir(&opt.log_dir, &prj)?;
+        println!("Run {}, logs in {}", run_id, run_dir.display());
+        Some((run_id, run_dir))
+    };
+    let mut build_opt = BuildOpt {
+        log_dir: run
+            .as_ref()
+            .map(|(_, run_dir)| run_dir.clone())
+            .unwrap_or_default(),
+        ..BuildOpt::default()
+    };
+    let started = chrono::Local::now();
+    let (build_queue, gh, error) = match prepare_build(&opt, &prj, rev.as_ref(), &mut build_opt) {
+        Ok((build_queue, gh)) => (build_queue, gh, None),
+        Err(e) => (Vec::new(), Github::default(), Some(e)),
+    };
+    let (reports, error) = match error {
+        None => build(&build_queue, &prj, &opt, &build_opt, &gh),
+        Some(e) => (Vec::new(), Some(e)),
     };
-    let (rs, _) = get_status(&prj, &config.tokens.github).with_context(|_| {
-        format!(
-            "Fetch repository data failed for user {} project {}",
-            prj.owner, prj.project,
+    if let Some((run_id, run_dir)) = &run {
+        let reference = rev.clone().or_else(|| opt.tag_name.clone());
+        let manifest = RunManifest::new(
+            run_id,
+            &prj,
+            reference,
+            &build_opt,
+            started,
+            &reports,
+            error.as_ref(),
+        );
+        write_manifest(run_dir, &manifest)?;
+        append_run(&opt.log_dir, &manifest)?;
+        let project_dir = get_project_log_dir(&opt.log_dir, &prj);
+        for d in prune_runs(&project_dir, opt.keep_runs, opt.keep_days, started)? {
+            println!("Removed the old logs in {}", d.display());
+        }
+    }
+    if !reports.is_empty() {
+        print!("\n{}", format_summary(&reports));
+    }
+    if let Some(e) = error {
+        return Err(ExitFailure::from(e));
+    }
+    check_reports(&reports)?;
+    Ok(())
+}
+
+/// Read the configuration, fetch the source and read its YAML file and fetch
+/// or create the release of the tag, if any: the build queue is returned
+fn prepare_build(
+    opt: &Opt,
+    prj: &Project,
+    rev: Option<&String>,
+    build_opt: &mut BuildOpt,
+) -> Result<(Vec<BuildJob>, Github), failure::Error> {
+    /* the configuration file is needed only to access github */
+    let config = if opt.source.is_none() {
+        debug!(
+            "Reading configuration file {}",
+            opt.configfile
+                .to_str()
+                .unwrap_or("Filename not convertible")
+        );
+        Some(
+            crate::config::get_config(&opt.configfile).with_context(|_| {
+                format!(
+                    "could not parse the file {}",
+                    opt.configfile.to_str().unwrap_or("file name not printable")
+                )
+            })?,
         )
-    })?;
-    println!("github repository information:\n{}", rs);
+    } else {
+        None
+    };
+    let gh = config
+        .map(|c| Github {
+            urls: c.github,
+            token: c.tokens.github,
+        })
+        .unwrap_or_default();
     /* fetch the repo to read the .bsd-ci file */
-    let path = crate::pot::fetch_git_in_fscomp(&prj, &rs, &opt).with_context(|_| {
+    let fetched = match &opt.source {
+        Some(Source::Path(src)) => crate::pot::copy_in_fscomp(prj, src, opt),
+        Some(Source::Url(url)) => match rev {
+            Some(rev) => crate::pot::fetch_rev_in_fscomp(prj, url.as_str(), rev, opt),
+            None => crate::pot::fetch_git_in_fscomp(prj, url.as_str(), opt),
+        },
+        None => {
+            let (rs, _) = get_status(prj, &gh).with_context(|_| {
+                format!(
+                    "Fetch repository data failed for user {} project {}",
+                    prj.owner, prj.project,
+                )
+            })?;
+            println!("github repository information:\n{}", rs);
+            match rev {
+                Some(rev) => crate::pot::fetch_rev_in_fscomp(prj, rs.url.as_str(), rev, opt),
+                None => crate::pot::fetch_git_in_fscomp(prj, rs.url.as_str(), opt),
+            }
+        }
+    };
+    let path = fetched.with_context(|_| {
         "Failed to create a ZFS dataset with the project in it\n Is pot installed?\n Are you root?"
     })?;
 
-    println!("Git repo fetched in {}", path);
+    println!("Source fetched in {}", path);
+    let sha = crate::pot::get_git_sha(&path);
+    // local sources are not on github
+    let status_sha = match (
+        opt.commit_status_flag && opt.source.is_none(),
+        opt.merge_flag,
+    ) {
+        (false, _) => None,
+        (true, false) => sha.clone(),
+        // the merge of a pull request is not shown on github, its head is
+        (true, true) => {
+            let head = crate::pot::get_merge_head(&path);
+            if head.is_none() {
+                println!("No commit statuses: the head of the pull request not found");
+            }
+            head
+        }
+    };
+    build_opt.sha = sha;
+    build_opt.status_sha = status_sha;
+    if let Some(sha) = &build_opt.sha {
+        println!("Commit: {}", sha);
+    }
 
-    let mut build_queue = Vec::new();
-    let mut build_opt = BuildOpt::default();
     let yaml_string =
         get_yaml(&path).with_context(|_| "Error accessing the yaml file in the project")?;
-    let docs = YamlLoader::load_from_str(&yaml_string)
-        .with_context(|_| "Error parsing the yaml file in the project")?;
-    for d in docs {
-        let h = d.into_hash().unwrap();
-        debug!("{:?}", h);
-        let lang = get_lang(&h).with_context(|_| "Invalid YAML")?;
-        let os = get_os(&h).with_context(|_| "Invalid YAML")?;
-        let build_lang = match lang.as_ref() {
-            "rust" => get_build_lang("rust", &h)
-                .with_context(|_| format!("Ivalid YAML for language {}", lang))?,
-            _ => {
-                return Err(ExitFailure::from(ParseError::GenericError {
-                    msg: "language not supported".to_string(),
-                }));
-            }
-        };
-        let build_os = match os.as_ref() {
-            "FreeBSD" => get_build_os("FreeBSD", &h)?,
-            _ => {
-                return Err(ExitFailure::from(ParseError::GenericError {
-                    msg: "os not supported".to_string(),
-                }));
-            }
-        };
-        build_opt.update = get_update(&h)?;
-        info!("{:?}", build_lang);
-        info!("{:?}", build_os);
-        for o in &build_os {
-            for l in &build_lang {
-                build_queue.push(BuildJob {
-                    lang: l.clone(),
-                    os: o.clone(),
-                    deploy: true,
-                });
-                debug!("o {:?} - l {:?}", o, l);
-            }
+    let ci_config = parse_config(&yaml_string, &get_yaml_path(&path).to_string_lossy())
+        .with_context(|_| "Invalid YAML")?;
+    build_opt.update = ci_config.update;
+    build_opt.build_system = get_build_system(&ci_config);
+    build_opt.timeout = ci_config.timeout.or(opt.timeout);
+    build_opt.stall_timeout = ci_config.stall_timeout.or(opt.stall_timeout);
+    let mut build_queue = get_build_queue(&ci_config)?;
+    if opt.pull_request.is_some() {
+        // pull requests are never deployed
+        for j in build_queue.iter_mut() {
+            j.deploy = false;
         }
-        get_no_deploy(&h, &mut build_queue)?;
-        print_jobs(&build_queue);
     }
-    if let Some(tag_name) = &opt.tag_name {
-        if let Ok((release_id, assets, _)) = get_release_id(&prj, tag_name, &config.tokens.github) {
+    print_jobs(&build_queue);
+    if let (Some(tag_name), None, None) = (&opt.tag_name, &opt.source, opt.pull_request) {
+        let notes = if opt.no_changelog_notes_flag {
+            None
+        } else {
+            get_changelog_notes(&path, tag_name)
+        };
+        let release = get_release(prj, tag_name, notes.as_deref(), opt, &gh)?;
+        if let Some((release_id, assets)) = release {
             build_opt.release_id = Some(release_id);
             build_opt.assets = assets;
         }
     }
-    build(&build_queue, &prj, &opt, &build_opt, &config.tokens.github)?;
-    Ok(())
+    Ok((build_queue, gh))
 }
 
 #[cfg(test)]
@@ -238,11 +660,7 @@ mod tests {
                 os_family: "FreeBSD".to_string(),
                 os_version: "11.2".to_string(),
             },
-            lang: BuildLang {
-                lang: "rust".to_string(),
-                lang_variant: "stable".to_string(),
-            },
-            deploy: true,
+            ..BuildJob::test("rust", "stable")
         };
         let rc = uut.to_string();
         assert_eq!(&rc, "FreeBSD-11_2-rust-stable");
@@ -250,20 +668,45 @@ mod tests {
     #[test]
     fn test_job_to_string_php() {
         let uut = BuildJob {
-            os: BuildOS {
-                os_family: "FreeBSD".to_string(),
-                os_version: "12.0".to_string(),
-            },
-            lang: BuildLang {
-                lang: "php".to_string(),
-                lang_variant: "7.3".to_string(),
-            },
             deploy: false,
+            ..BuildJob::test("php", "7.3")
         };
         let rc = uut.to_string();
         assert_eq!(&rc, "FreeBSD-12_0-php-7_3");
     }
 
+    #[test]
+    fn test_pull_request_ref() {
+        assert_eq!(&pull_request_ref(42, false), "refs/pull/42/head");
+        assert_eq!(&pull_request_ref(42, true), "refs/pull/42/merge");
+    }
+
+    #[test]
+    fn test_is_release_body_replaced() {
+        assert!(is_release_body_replaced(None, false));
+        assert!(is_release_body_replaced(Some(" \n"), false));
+        assert!(!is_release_body_replaced(Some("Bug fixes"), false));
+        assert!(is_release_body_replaced(Some("Bug fixes"), true));
+    }
+
+    #[test]
+    fn test_parse_keep_runs() {
+        assert_eq!(parse_keep_runs("3"), Ok(3));
+        assert!(parse_keep_runs("0").is_err());
+        assert!(parse_keep_runs("-1").is_err());
+    }
+
+    #[test]
+    fn test_parse_source() {
+        let s = parse_source("file:///home/user/ci-test.git").unwrap();
+        assert_eq!(s.project_name(), Some("ci-test".to_string()));
+        assert!(parse_source("https://github.com/pizzamig/ci-test").is_err());
+        let s = parse_source("src").unwrap();
+        assert_eq!(s, Source::Path(PathBuf::from("src")));
+        assert_eq!(s.project_name(), Some("src".to_string()));
+        assert!(parse_source("./no-such-directory").is_err());
+    }
+
     #[test]
     fn test_project_to_string_ok() {
         let uut = Project {
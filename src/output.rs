@@ -0,0 +1,200 @@
+use chrono::Local;
+use std::collections::VecDeque;
+use std::fmt::Display;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// The number of lines of output kept, to be reported if the build stalls
+const TAIL_LINES: usize = 20;
+
+/// The output streams of a build script
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Stream {
+    Stdout,
+    Stderr,
+}
+
+impl Display for Stream {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let s = match self {
+            Stream::Stdout => "out",
+            Stream::Stderr => "err",
+        };
+        f.pad(s)
+    }
+}
+
+/// The recent output of a build script: when it was written and its last lines
+#[derive(Debug)]
+struct Activity {
+    last_output: Instant,
+    tail: VecDeque<String>,
+}
+
+impl Activity {
+    fn push_line(&mut self, line: &str) {
+        if self.tail.len() == TAIL_LINES {
+            self.tail.pop_front();
+        }
+        self.tail.push_back(line.to_string());
+    }
+}
+
+/// The output of a running build script, shared by the threads copying its
+/// streams: every line is printed on the terminal, prefixed with the job,
+/// the stream and the elapsed time, and written to the combined log, with
+/// its timestamp, keeping the order of stdout and stderr
+#[derive(Debug)]
+pub(crate) struct BuildOutput {
+    job: String,
+    start: Instant,
+    combined_log: Mutex<File>,
+    activity: Mutex<Activity>,
+}
+
+impl BuildOutput {
+    pub(crate) fn new(job: &str, combined_log: File) -> Arc<BuildOutput> {
+        let start = Instant::now();
+        Arc::new(BuildOutput {
+            job: job.to_string(),
+            start,
+            combined_log: Mutex::new(combined_log),
+            activity: Mutex::new(Activity {
+                last_output: start,
+                tail: VecDeque::with_capacity(TAIL_LINES),
+            }),
+        })
+    }
+
+    /// The time elapsed since the last output
+    pub(crate) fn idle(&self) -> Duration {
+        self.activity.lock().unwrap().last_output.elapsed()
+    }
+
+    /// The last lines of output, oldest first
+    pub(crate) fn tail(&self) -> Vec<String> {
+        self.activity.lock().unwrap().tail.iter().cloned().collect()
+    }
+
+    fn touch(&self) {
+        self.activity.lock().unwrap().last_output = Instant::now();
+    }
+
+    fn write_line(&self, stream: Stream, line: &[u8]) -> io::Result<()> {
+        let line = String::from_utf8_lossy(line);
+        let elapsed = format_elapsed(self.start.elapsed());
+        println!("[{} {} {}] {}", self.job, stream, elapsed, line);
+        let mut combined_log = self.combined_log.lock().unwrap();
+        writeln!(
+            combined_log,
+            "{} {} {}",
+            Local::now().format("%Y-%m-%dT%H:%M:%S%.3f"),
+            stream,
+            line
+        )?;
+        self.activity.lock().unwrap().push_line(&line);
+        Ok(())
+    }
+}
+
+/// The last line of output in the combined log, without its timestamp and
+/// its stream
+pub(crate) fn get_last_line(combined_log: &Path) -> Option<String> {
+    let log = fs::read_to_string(combined_log).ok()?;
+    let line = log.lines().rev().find(|l| !l.trim().is_empty())?;
+    line.splitn(3, ' ').nth(2).map(|l| l.trim().to_string())
+}
+
+fn format_elapsed(d: Duration) -> String {
+    let secs = d.as_secs();
+    format!("{:02}:{:02}", secs / 60, secs % 60)
+}
+
+/// Copy a stream of the build script to its own log file and to the build
+/// output, in a new thread; the thread ends when the stream is closed
+pub(crate) fn copy_output<R: Read + Send + 'static>(
+    mut output: R,
+    stream: Stream,
+    mut log: File,
+    build_output: Arc<BuildOutput>,
+) -> JoinHandle<io::Result<()>> {
+    thread::spawn(move || {
+        let mut buf = [0u8; 4096];
+        // the last line, not terminated yet
+        let mut line = Vec::new();
+        loop {
+            let n = match output.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            log.write_all(&buf[..n])?;
+            build_output.touch();
+            for &c in &buf[..n] {
+                if c == b'\n' {
+                    build_output.write_line(stream, &line)?;
+                    line.clear();
+                } else {
+                    line.push(c);
+                }
+            }
+        }
+        if !line.is_empty() {
+            build_output.write_line(stream, &line)?;
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_format_elapsed() {
+        assert_eq!(format_elapsed(Duration::from_secs(7)), "00:07");
+        assert_eq!(format_elapsed(Duration::from_secs(3725)), "62:05");
+    }
+    #[test]
+    fn test_copy_output() {
+        let dir = std::env::temp_dir();
+        let log_path = dir.join("freebsd-ci-test-copy-output.log");
+        let combined_path = dir.join("freebsd-ci-test-copy-output_combined.log");
+        let build_output = BuildOutput::new("job", File::create(&combined_path).unwrap());
+        let text: String = (1..=25).map(|i| format!("line {}\n", i)).collect();
+        let output = Cursor::new(format!("{}last", text).into_bytes());
+        let log = File::create(&log_path).unwrap();
+        copy_output(output, Stream::Stdout, log, build_output.clone())
+            .join()
+            .unwrap()
+            .unwrap();
+        let err = Cursor::new(b"error\n".to_vec());
+        let log = File::create(&log_path).unwrap();
+        copy_output(err, Stream::Stderr, log, build_output.clone())
+            .join()
+            .unwrap()
+            .unwrap();
+        let log = std::fs::read_to_string(&log_path).unwrap();
+        let combined = std::fs::read_to_string(&combined_path).unwrap();
+        assert_eq!(log, "error\n");
+        let combined: Vec<&str> = combined.lines().collect();
+        assert_eq!(combined.len(), 27);
+        assert!(combined[0].ends_with(" out line 1"));
+        assert!(combined[25].ends_with(" out last"));
+        assert!(combined[26].ends_with(" err error"));
+        assert_eq!(get_last_line(&combined_path), Some("error".to_string()));
+        std::fs::remove_file(&log_path).unwrap();
+        std::fs::remove_file(&combined_path).unwrap();
+        let tail = build_output.tail();
+        assert_eq!(tail.len(), TAIL_LINES);
+        assert_eq!(tail.first().unwrap(), "line 8");
+        assert_eq!(tail.last().unwrap(), "error");
+        assert!(build_output.idle() < Duration::from_secs(60));
+    }
+}
@@ -1,9 +1,9 @@
-use crate::github::RepoStatus;
 use crate::Opt;
 use crate::Project;
 use failure::{Error, Fail};
 use log::debug;
-use std::process::Command;
+use std::path::Path;
+use std::process::{Command, Output};
 use std::{thread, time};
 
 pub(crate) fn is_pot_available() -> bool {
@@ -26,6 +26,8 @@ pub(crate) enum PotError {
     FscompSnapshotFailed { name: String, snap: String },
     #[fail(display = "Fscomp revert failed on {}@{}", name, snap)]
     FscompRevertFailed { name: String, snap: String },
+    #[fail(display = "Fscomp clone failed on {} from {}", name, parent)]
+    FscompCloneFailed { name: String, parent: String },
     #[fail(display = "Fscomp destroy failed on {}", name)]
     FscompDestroyFailed { name: String },
     #[fail(display = "Pot {} already present", name)]
@@ -57,6 +59,19 @@ pub(crate) enum PotError {
         tag: String,
         stderr: String,
     },
+    #[fail(display = "Git fetch failed from {} to {} of {}", url, path, rev)]
+    GitFetchFailed {
+        url: String,
+        path: String,
+        rev: String,
+        stderr: String,
+    },
+    #[fail(display = "Copy failed from {} to {}", src, path)]
+    CopyFailed {
+        src: String,
+        path: String,
+        stderr: String,
+    },
 }
 
 fn _is_fscomp_present(fscompname: &str) -> Result<bool, Error> {
@@ -123,31 +138,50 @@ fn _destroy_pot(pot_name: &str) -> Result<(), Error> {
     }
 }
 
-pub(crate) fn destroy_pot(pot_name: &str) -> Result<(), Error> {
-    let output = Command::new("pot").args(&["stop", &pot_name]).output()?;
+fn is_pot_running(pot_name: &str) -> bool {
+    match Command::new("pot")
+        .args(["info", "-r", "-p", pot_name])
+        .output()
+    {
+        Ok(output) => output.status.success(),
+        Err(_e) => false,
+    }
+}
+
+/// Stop the pot, if it's running
+pub(crate) fn stop_pot(pot_name: &str) -> Result<(), Error> {
+    if !is_pot_running(pot_name) {
+        debug!("pot {} not running", pot_name);
+        return Ok(());
+    }
+    let output = Command::new("pot").args(["stop", pot_name]).output()?;
     if !output.status.success() {
-        Err(Error::from(PotError::PotStopFailed {
+        return Err(Error::from(PotError::PotStopFailed {
             name: pot_name.to_string(),
-        }))
-    } else {
-        let ten_minutes = time::Duration::from_millis(10 * 60 * 1000);
-        let ten_seconds = time::Duration::from_millis(10 * 1000);
-        let start_timestamp = time::Instant::now();
-        loop {
-            if _destroy_pot(pot_name).is_ok() {
-                println!("Destroyed pot {}", pot_name);
-                break;
-            }
-            if start_timestamp.elapsed() < ten_minutes {
-                thread::sleep(ten_seconds);
-            } else {
-                return Err(Error::from(PotError::PotDestroyFailed {
-                    name: pot_name.to_string(),
-                }));
-            }
+        }));
+    }
+    Ok(())
+}
+
+pub(crate) fn destroy_pot(pot_name: &str) -> Result<(), Error> {
+    stop_pot(pot_name)?;
+    let ten_minutes = time::Duration::from_millis(10 * 60 * 1000);
+    let ten_seconds = time::Duration::from_millis(10 * 1000);
+    let start_timestamp = time::Instant::now();
+    loop {
+        if _destroy_pot(pot_name).is_ok() {
+            println!("Destroyed pot {}", pot_name);
+            break;
+        }
+        if start_timestamp.elapsed() < ten_minutes {
+            thread::sleep(ten_seconds);
+        } else {
+            return Err(Error::from(PotError::PotDestroyFailed {
+                name: pot_name.to_string(),
+            }));
         }
-        Ok(())
     }
+    Ok(())
 }
 
 pub(crate) fn revert_fscomp(fscomp_name: &str) -> Result<(), Error> {
@@ -189,13 +223,9 @@ pub(crate) fn get_pot_path(pot_name: &str) -> Result<String, Error> {
     Ok(pot_path)
 }
 
-pub(crate) fn fetch_git_in_fscomp(
-    repo: &Project,
-    repo_status: &RepoStatus,
-    config: &Opt,
-) -> Result<String, Error> {
+/// Create the fscomp of the project, destroying the old one if forced
+fn create_project_fscomp(repo: &Project, config: &Opt) -> Result<String, Error> {
     let fscomp_name = repo.to_string();
-    let fscomp_path = get_fscomp_path(&fscomp_name)?;
     if is_fscomp_present(&fscomp_name) {
         debug!("fscomp {} found", fscomp_name);
         if config.force_flag {
@@ -219,22 +249,38 @@ pub(crate) fn fetch_git_in_fscomp(
             name: fscomp_name,
         }));
     }
+    get_fscomp_path(&fscomp_name)
+}
+
+/// Take the snapshot used to revert the fscomp after every build
+fn snapshot_project_fscomp(repo: &Project) -> Result<(), Error> {
+    let fscomp_name = repo.to_string();
+    let output = Command::new("pot")
+        .args(&["snapshot", "-f", &fscomp_name])
+        .output()?;
+    if !output.status.success() {
+        return Err(Error::from(PotError::FscompSnapshotFailed {
+            name: fscomp_name,
+            snap: "source_only".to_string(),
+        }));
+    }
+    Ok(())
+}
+
+pub(crate) fn fetch_git_in_fscomp(
+    repo: &Project,
+    url: &str,
+    config: &Opt,
+) -> Result<String, Error> {
+    let fscomp_path = create_project_fscomp(repo, config)?;
     /* git clone in it */
     if let Some(tag) = &config.tag_name {
         let output = Command::new("git")
-            .args(&[
-                "clone",
-                "--depth",
-                "1",
-                "--branch",
-                tag,
-                repo_status.url.as_str(),
-                &fscomp_path,
-            ])
+            .args(&["clone", "--depth", "1", "--branch", tag, url, &fscomp_path])
             .output()?;
         if !output.status.success() {
             return Err(Error::from(PotError::GitCloneTagFailed {
-                url: repo_status.url.as_str().to_string(),
+                url: url.to_string(),
                 path: fscomp_path,
                 tag: tag.to_string(),
                 stderr: String::from_utf8(output.stderr)
@@ -243,17 +289,11 @@ pub(crate) fn fetch_git_in_fscomp(
         }
     } else {
         let output = Command::new("git")
-            .args(&[
-                "clone",
-                "--depth",
-                "1",
-                repo_status.url.as_str(),
-                &fscomp_path,
-            ])
+            .args(&["clone", "--depth", "1", url, &fscomp_path])
             .output()?;
         if !output.status.success() {
             return Err(Error::from(PotError::GitCloneFailed {
-                url: repo_status.url.as_str().to_string(),
+                url: url.to_string(),
                 path: fscomp_path,
                 stderr: String::from_utf8(output.stderr)
                     .unwrap_or_else(|_| "stderr not available".to_string()),
@@ -261,26 +301,172 @@ pub(crate) fn fetch_git_in_fscomp(
         }
     }
     /* take a snapshot */
-    let output = Command::new("pot")
-        .args(&["snapshot", "-f", &fscomp_name])
+    snapshot_project_fscomp(repo)?;
+    Ok(fscomp_path)
+}
+
+fn git_in(path: &str, args: &[&str]) -> Result<Output, Error> {
+    debug!("git -C {} {}", path, args.join(" "));
+    Ok(Command::new("git")
+        .arg("-C")
+        .arg(path)
+        .args(args)
+        .output()?)
+}
+
+/// The refs fetched when the revision can't be fetched alone: the branches,
+/// the tags and the pull requests
+const FETCH_ALL_REFSPECS: &[&str] = &[
+    "+refs/heads/*:refs/remotes/origin/*",
+    "+refs/tags/*:refs/tags/*",
+    "+refs/pull/*:refs/remotes/origin/pull/*",
+];
+
+/// The arguments of `git fetch`: a shallow fetch of the revision or, without
+/// it, a fetch of all the refs
+fn get_fetch_args<'a>(url: &'a str, rev: Option<&'a str>) -> Vec<&'a str> {
+    let mut args = vec!["fetch", "-q"];
+    match rev {
+        Some(rev) => args.extend(&["--depth", "1", url, rev]),
+        None => {
+            args.push(url);
+            args.extend(FETCH_ALL_REFSPECS);
+        }
+    }
+    args
+}
+
+/// The revision to check out after fetching all the refs: a branch or a pull
+/// request is found under `refs/remotes/origin`, anything else (a tag or a
+/// SHA) is resolved as it is
+fn get_checkout_target(path: &str, rev: &str) -> String {
+    let remote = match rev.strip_prefix("refs/") {
+        Some(r) if r.starts_with("tags/") => None,
+        Some(r) => Some(r.strip_prefix("heads/").unwrap_or(r)),
+        None => Some(rev),
+    };
+    remote
+        .map(|r| format!("refs/remotes/origin/{}", r))
+        .filter(|r| {
+            git_in(path, &["rev-parse", "-q", "--verify", r])
+                .map(|o| o.status.success())
+                .unwrap_or(false)
+        })
+        .unwrap_or_else(|| rev.to_string())
+}
+
+/// Fetch exactly the given revision in `path` and check it out
+///
+/// If the server refuses a shallow fetch of the revision (i.e. an abbreviated
+/// SHA), all the branches, tags and pull requests are fetched
+fn fetch_rev(path: &str, url: &str, rev: &str) -> Result<(), Error> {
+    let git_err = |output: Output| {
+        Error::from(PotError::GitFetchFailed {
+            url: url.to_string(),
+            path: path.to_string(),
+            rev: rev.to_string(),
+            stderr: String::from_utf8(output.stderr)
+                .unwrap_or_else(|_| "stderr not available".to_string()),
+        })
+    };
+    let output = git_in(path, &["init", "-q"])?;
+    if !output.status.success() {
+        return Err(git_err(output));
+    }
+    let output = git_in(path, &get_fetch_args(url, Some(rev)))?;
+    let target = if output.status.success() {
+        "FETCH_HEAD".to_string()
+    } else {
+        debug!("shallow fetch of {} failed, fetching all the refs", rev);
+        let output = git_in(path, &get_fetch_args(url, None))?;
+        if !output.status.success() {
+            return Err(git_err(output));
+        }
+        get_checkout_target(path, rev)
+    };
+    let output = git_in(path, &["checkout", "-q", "--detach", &target])?;
+    if !output.status.success() {
+        return Err(git_err(output));
+    }
+    Ok(())
+}
+
+/// Fetch in the fscomp of the project exactly the given revision: a branch, a
+/// tag, a reference or a commit SHA
+pub(crate) fn fetch_rev_in_fscomp(
+    repo: &Project,
+    url: &str,
+    rev: &str,
+    config: &Opt,
+) -> Result<String, Error> {
+    let fscomp_path = create_project_fscomp(repo, config)?;
+    fetch_rev(&fscomp_path, url, rev)?;
+    /* take a snapshot */
+    snapshot_project_fscomp(repo)?;
+    Ok(fscomp_path)
+}
+
+/// The SHA of the commit checked out in the given path, if it's a git repository
+pub(crate) fn get_git_sha(path: &str) -> Option<String> {
+    let output = git_in(path, &["rev-parse", "HEAD"]).ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let sha = String::from_utf8(output.stdout).ok()?;
+    Some(sha.trim_end().to_string())
+}
+
+/// The SHA of the second parent of the merge commit checked out in the given
+/// path, like the head of a pull request merged by github; the commit object
+/// lists its parents even in a shallow clone
+pub(crate) fn get_merge_head(path: &str) -> Option<String> {
+    let output = git_in(path, &["cat-file", "-p", "HEAD"]).ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let commit = String::from_utf8(output.stdout).ok()?;
+    commit
+        .lines()
+        .take_while(|l| !l.is_empty())
+        .filter_map(|l| l.strip_prefix("parent "))
+        .nth(1)
+        .map(|p| p.to_string())
+}
+
+/// Copy a local directory, as it is, in the fscomp of the project
+pub(crate) fn copy_in_fscomp(repo: &Project, src: &Path, config: &Opt) -> Result<String, Error> {
+    let fscomp_path = create_project_fscomp(repo, config)?;
+    let mut src_dir = src.as_os_str().to_os_string();
+    src_dir.push("/.");
+    let output = Command::new("cp")
+        .arg("-Rp")
+        .arg(&src_dir)
+        .arg(&fscomp_path)
         .output()?;
     if !output.status.success() {
-        return Err(Error::from(PotError::FscompSnapshotFailed {
-            name: fscomp_name,
-            snap: "source_only".to_string(),
+        return Err(Error::from(PotError::CopyFailed {
+            src: src.to_string_lossy().to_string(),
+            path: fscomp_path,
+            stderr: String::from_utf8(output.stderr)
+                .unwrap_or_else(|_| "stderr not available".to_string()),
         }));
     }
+    /* take a snapshot */
+    snapshot_project_fscomp(repo)?;
     Ok(fscomp_path)
 }
 
+/// The name of the pot cloned from `parent_pot` to build the fscomp
+pub(crate) fn get_builder_pot_name(parent_pot: &str, fscomp_name: &str) -> String {
+    format!("{}-{}", parent_pot, fscomp_name)
+}
+
 pub(crate) fn spawn_builder_pot(
     parent_pot: &str,
     fscomp_name: &str,
     config: &Opt,
 ) -> Result<String, Error> {
-    let mut pot_name = parent_pot.to_string();
-    pot_name.push('-');
-    pot_name.push_str(fscomp_name);
+    let pot_name = get_builder_pot_name(parent_pot, fscomp_name);
     if is_pot_present(&pot_name) {
         if config.force_flag {
             _destroy_pot(&pot_name)?;
@@ -298,24 +484,175 @@ pub(crate) fn spawn_builder_pot(
         }));
     }
     // attach the fscomp to the cloned pot
+    mount_in_pot(&pot_name, fscomp_name, "/mnt")?;
+    Ok(pot_name)
+}
+
+/// Clone a fscomp from its last snapshot, destroying the old clone if forced
+pub(crate) fn clone_fscomp(parent: &str, fscomp_name: &str, config: &Opt) -> Result<(), Error> {
+    if is_fscomp_present(fscomp_name) {
+        if config.force_flag {
+            destroy_fscomp(fscomp_name)?;
+        } else {
+            return Err(Error::from(PotError::FscompAlreadyPresent {
+                name: fscomp_name.to_string(),
+            }));
+        }
+    }
     let output = Command::new("pot")
-        .args(&[
-            "mount-in",
-            "-p",
-            &pot_name,
-            "-f",
-            &fscomp_name,
-            "-m",
-            "/mnt",
-        ])
+        .args(&["clone-fscomp", "-F", parent, "-f", fscomp_name])
+        .output()?;
+    if !output.status.success() {
+        return Err(Error::from(PotError::FscompCloneFailed {
+            name: fscomp_name.to_string(),
+            parent: parent.to_string(),
+        }));
+    }
+    Ok(())
+}
+
+/// Attach a fscomp to a pot, at the given mount point
+pub(crate) fn mount_in_pot(pot_name: &str, fscomp_name: &str, mnt: &str) -> Result<(), Error> {
+    let output = Command::new("pot")
+        .args(&["mount-in", "-p", pot_name, "-f", fscomp_name, "-m", mnt])
         .output()?;
     if !output.status.success() {
         return Err(Error::from(PotError::MountInFailed {
-            pot: pot_name,
+            pot: pot_name.to_string(),
             fscomp: fscomp_name.to_string(),
-            mnt: "/mnt".to_string(),
+            mnt: mnt.to_string(),
         }));
     }
+    Ok(())
+}
 
-    Ok(pot_name)
+/// Create a cache fscomp, if not already present; it's never reverted nor
+/// destroyed, so its content is kept across builds
+pub(crate) fn create_cache_fscomp(fscomp_name: &str) -> Result<(), Error> {
+    if is_fscomp_present(fscomp_name) {
+        debug!("cache fscomp {} found", fscomp_name);
+        return Ok(());
+    }
+    let output = Command::new("pot")
+        .args(&["create-fscomp", "-f", fscomp_name])
+        .output()?;
+    if !output.status.success() {
+        return Err(Error::from(PotError::FscompCreateFailed {
+            name: fscomp_name.to_string(),
+        }));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn git(path: &Path, args: &[&str]) -> String {
+        let output = Command::new("git")
+            .args(["-c", "user.name=ci", "-c", "user.email=ci@example.com"])
+            .arg("-C")
+            .arg(path)
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "git {:?}: {:?}", args, output);
+        String::from_utf8(output.stdout)
+            .unwrap()
+            .trim_end()
+            .to_string()
+    }
+
+    /// A repository with a commit on master, one on the branch `feature`
+    /// and one only on the pull request 1; it returns their SHAs
+    fn create_repo(path: &Path) -> (String, String, String) {
+        std::fs::create_dir_all(path).unwrap();
+        git(path, &["init", "-q"]);
+        git(path, &["checkout", "-q", "-b", "master"]);
+        git(path, &["commit", "-q", "--allow-empty", "-m", "first"]);
+        let master = git(path, &["rev-parse", "HEAD"]);
+        git(path, &["checkout", "-q", "-b", "feature"]);
+        git(path, &["commit", "-q", "--allow-empty", "-m", "feature"]);
+        let feature = git(path, &["rev-parse", "HEAD"]);
+        git(path, &["checkout", "-q", "-b", "pr"]);
+        git(
+            path,
+            &["commit", "-q", "--allow-empty", "-m", "pull request"],
+        );
+        let pull = git(path, &["rev-parse", "HEAD"]);
+        git(path, &["update-ref", "refs/pull/1/head", &pull]);
+        // the merge of the pull request, made by github
+        git(path, &["checkout", "-q", "master"]);
+        git(path, &["checkout", "-q", "--detach"]);
+        git(path, &["merge", "-q", "--no-ff", "--no-edit", "pr"]);
+        let merge = git(path, &["rev-parse", "HEAD"]);
+        git(path, &["update-ref", "refs/pull/1/merge", &merge]);
+        git(path, &["checkout", "-q", "master"]);
+        git(path, &["branch", "-q", "-D", "pr"]);
+        (master, feature, pull)
+    }
+
+    #[test]
+    fn test_get_fetch_args() {
+        assert_eq!(
+            get_fetch_args("file:///src", Some("main")),
+            vec!["fetch", "-q", "--depth", "1", "file:///src", "main"]
+        );
+        let args = get_fetch_args("file:///src", None);
+        assert_eq!(args[..3], ["fetch", "-q", "file:///src"]);
+        assert_eq!(args[3..], *FETCH_ALL_REFSPECS);
+    }
+    #[test]
+    fn test_fetch_rev() {
+        let root = std::env::temp_dir().join("freebsd-ci-test-fetch-rev");
+        let _ = std::fs::remove_dir_all(&root);
+        let src = root.join("src");
+        let (master, feature, pull) = create_repo(&src);
+        let url = format!("file://{}", src.display());
+        let checkout = |name: &str, rev: &str| {
+            let path = root.join(name);
+            std::fs::create_dir_all(&path).unwrap();
+            fetch_rev(&path.to_string_lossy(), &url, rev).unwrap();
+            git(&path, &["rev-parse", "HEAD"])
+        };
+        // a shallow fetch
+        assert_eq!(checkout("branch", "feature"), feature);
+        assert_eq!(checkout("ref", "refs/pull/1/head"), pull);
+        // abbreviated SHAs are fetched with all the refs
+        assert_eq!(checkout("sha", &master[..10]), master);
+        assert_eq!(checkout("pull", &pull[..10]), pull);
+        // after the fallback, the branches are found under origin
+        let path = root.join("fallback");
+        std::fs::create_dir_all(&path).unwrap();
+        let path = path.to_string_lossy();
+        git_in(&path, &["init", "-q"]).unwrap();
+        git_in(&path, &get_fetch_args(&url, None)).unwrap();
+        let branch = get_checkout_target(&path, "feature");
+        let full_ref = get_checkout_target(&path, "refs/heads/feature");
+        let pull_ref = get_checkout_target(&path, "refs/pull/1/head");
+        let sha = get_checkout_target(&path, &master[..10]);
+        std::fs::remove_dir_all(&root).unwrap();
+        assert_eq!(branch, "refs/remotes/origin/feature");
+        assert_eq!(full_ref, "refs/remotes/origin/feature");
+        assert_eq!(pull_ref, "refs/remotes/origin/pull/1/head");
+        assert_eq!(sha, master[..10]);
+    }
+    #[test]
+    fn test_get_merge_head() {
+        let root = std::env::temp_dir().join("freebsd-ci-test-merge-head");
+        let _ = std::fs::remove_dir_all(&root);
+        let src = root.join("src");
+        let (_, _, pull) = create_repo(&src);
+        let path = root.join("merge");
+        std::fs::create_dir_all(&path).unwrap();
+        let path = path.to_string_lossy();
+        let url = format!("file://{}", src.display());
+        fetch_rev(&path, &url, "refs/pull/1/merge").unwrap();
+        let merge_head = get_merge_head(&path);
+        let head = get_merge_head(&src.to_string_lossy());
+        std::fs::remove_dir_all(&root).unwrap();
+        assert_eq!(merge_head, Some(pull));
+        // not a merge
+        assert_eq!(head, None);
+    }
 }
@@ -1,275 +1,1490 @@
 use super::{BuildJob, BuildLang, BuildOS};
-use crate::error::ParseError;
+use crate::error::{ParseError, YamlDiagnostic, YamlDiagnostics};
+use crate::lang::{get_language, get_language_names};
 use log::debug;
 use log::error;
 use log::info;
+use serde::de::{self, DeserializeSeed, Deserializer, MapAccess, Visitor};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt;
 use std::fs::File;
 use std::io::prelude::*;
 use std::path::PathBuf;
+use yaml_rust::parser::{Event, MarkedEventReceiver, Parser};
+use yaml_rust::scanner::{Marker, TScalarStyle};
 
-pub(crate) fn get_yaml(repopath: &str) -> Result<String, std::io::Error> {
+/// The most recent version of the `.bsd-ci.yml` schema supported
+pub(crate) const SCHEMA_VERSION: u32 = 1;
+
+/// The keys with a fixed name; the os and language keys depend on the value
+/// of `os` and `language`
+const FIXED_KEYS: &[&str] = &[
+    "schema_version",
+    "os",
+    "language",
+    "build_system",
+    "update",
+    "timeout",
+    "stall_timeout",
+    "no_deploy",
+    "deploy_only",
+    "allow_failures",
+    "matrix",
+];
+
+/// The content of a `.bsd-ci.yml` file
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct CiConfig {
+    pub(crate) schema_version: u32,
+    pub(crate) os: String,
+    pub(crate) os_versions: Vec<String>,
+    pub(crate) language: String,
+    pub(crate) language_variants: Vec<String>,
+    pub(crate) build_system: Option<String>,
+    pub(crate) update: bool,
+    pub(crate) timeout: Option<u64>,
+    pub(crate) stall_timeout: Option<u64>,
+    pub(crate) no_deploy: Vec<JobSelector>,
+    pub(crate) deploy_only: Option<Vec<JobSelector>>,
+    pub(crate) allow_failures: Vec<JobSelector>,
+    pub(crate) matrix: Matrix,
+}
+
+/// A set of jobs: a field not specified matches any value
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct JobSelector {
+    pub(crate) os_family: Option<String>,
+    pub(crate) os_version: Option<String>,
+    pub(crate) language: Option<String>,
+    pub(crate) lang_variant: Option<String>,
+}
+
+impl JobSelector {
+    pub(crate) fn matches(&self, job: &BuildJob) -> bool {
+        self.os_family.iter().all(|v| *v == job.os.os_family)
+            && self.os_version.iter().all(|v| *v == job.os.os_version)
+            && self.language.iter().all(|v| *v == job.lang.lang)
+            && self
+                .lang_variant
+                .iter()
+                .all(|v| *v == job.lang.lang_variant)
+    }
+}
+
+/// A job added to the matrix, with its extra environment variables and its
+/// timeout
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct MatrixInclude {
+    pub(crate) os_version: String,
+    pub(crate) lang_variant: String,
+    pub(crate) env: BTreeMap<String, String>,
+    pub(crate) timeout: Option<u64>,
+}
+
+/// The rules applied to the product of os versions and language variants
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct Matrix {
+    pub(crate) include: Vec<MatrixInclude>,
+    pub(crate) exclude: Vec<JobSelector>,
+}
+
+pub(crate) fn get_yaml_path(repopath: &str) -> PathBuf {
     let mut yaml_file = PathBuf::new();
     yaml_file.push(repopath);
     yaml_file.push(".bsd-ci.yml");
-    let file = File::open(&yaml_file)?;
+    yaml_file
+}
+
+pub(crate) fn get_yaml(repopath: &str) -> Result<String, std::io::Error> {
+    let file = File::open(get_yaml_path(repopath))?;
     let mut reader = std::io::BufReader::new(file);
     let mut rc = String::new();
     reader.read_to_string(&mut rc)?;
     Ok(rc)
 }
 
-pub(crate) fn get_lang(h: &yaml_rust::yaml::Hash) -> Result<String, ParseError> {
-    if let Some(k_lang) = h.get(&yaml_rust::Yaml::from_str("language")) {
-        if let Some(lang) = k_lang.as_str() {
-            debug!("{:?}", lang);
-            return Ok(lang.to_string());
-        } else {
-            error!("language is not a string");
-            return Err(ParseError::InvalidType {
-                name: "language".to_string(),
-            });
-        }
-    } else {
-        error!("No language found!");
-        return Err(ParseError::MissingKey {
-            key: "language".to_string(),
-        });
+/// A string that has to be a string in the YAML file too: plain scalars
+/// like `11.2` or `true` are rejected instead of being converted
+struct YamlString(String);
+
+impl<'de> de::Deserialize<'de> for YamlString {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(YamlStringVisitor)
     }
 }
 
-pub(crate) fn get_os(h: &yaml_rust::yaml::Hash) -> Result<String, ParseError> {
-    if let Some(k_os) = h.get(&yaml_rust::Yaml::from_str("os")) {
-        if let Some(os) = k_os.as_str() {
-            debug!("{:?}", os);
-            return Ok(os.to_string());
-        } else {
-            error!("os is not a string");
-            return Err(ParseError::InvalidType {
-                name: "os".to_string(),
-            });
+struct YamlStringVisitor;
+
+impl<'de> Visitor<'de> for YamlStringVisitor {
+    type Value = YamlString;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a string")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<YamlString, E> {
+        Ok(YamlString(v.to_string()))
+    }
+}
+
+fn into_strings(v: Vec<YamlString>) -> Vec<String> {
+    v.into_iter().map(|x| x.0).collect()
+}
+
+enum Key {
+    SchemaVersion,
+    Os,
+    Language,
+    BuildSystem,
+    Update,
+    Timeout,
+    StallTimeout,
+    NoDeploy,
+    DeployOnly,
+    AllowFailures,
+    Matrix,
+    OsVersions,
+    LanguageVariants,
+    /// A key already reported, whose value is ignored
+    Skipped,
+}
+
+/// The names of the os and the language keys, known only after a first pass
+/// on the document, and the slot where the classified error is stored, as
+/// serde_yaml errors carry only a message and a position
+#[derive(Clone, Copy)]
+struct CiConfigSeed<'a> {
+    os: &'a str,
+    language: &'a str,
+    failed: &'a RefCell<Option<ParseError>>,
+    /// The problems that don't stop the parsing, like an unsupported variant
+    problems: &'a RefCell<Vec<ParseError>>,
+    /// The keys with an error reported by a previous pass, to be ignored
+    skipped: &'a [String],
+    /// The key whose value is being read
+    current: &'a RefCell<Option<String>>,
+}
+
+impl<'a> CiConfigSeed<'a> {
+    fn fail<E: de::Error>(self, e: ParseError) -> E {
+        let msg = e.to_string();
+        self.failed.replace(Some(e));
+        E::custom(msg)
+    }
+
+    fn next_value<'de, A, T>(self, map: &mut A, key: &str) -> Result<T, A::Error>
+    where
+        A: MapAccess<'de>,
+        T: de::Deserialize<'de>,
+    {
+        let rc = map.next_value();
+        if rc.is_err() {
+            self.failed.replace(Some(ParseError::InvalidType {
+                name: key.to_string(),
+            }));
         }
-    } else {
-        error!("No os found!");
-        return Err(ParseError::MissingKey {
-            key: "os".to_string(),
-        });
-    }
-}
-
-pub(crate) fn get_build_lang(
-    l: &str,
-    h: &yaml_rust::yaml::Hash,
-) -> Result<Vec<BuildLang>, ParseError> {
-    let mut result = Vec::new();
-    if let Some(lang) = h.get(&yaml_rust::Yaml::from_str(l)) {
-        if let Some(v_lang) = lang.as_vec() {
-            for v in v_lang {
-                if let Some(v_str) = v.as_str() {
-                    result.push(BuildLang {
-                        lang: l.to_string(),
-                        lang_variant: v_str.to_string(),
-                    });
-                } else {
-                    return Err(ParseError::GenericError {
-                        msg: "Language array has invalid type".to_string(),
+        rc
+    }
+
+    /// The value of `key`, a positive number of minutes
+    fn minutes<'de, A: MapAccess<'de>>(self, map: &mut A, key: &str) -> Result<u64, A::Error> {
+        let t: u64 = self.next_value(map, key)?;
+        if t == 0 {
+            return Err(self.fail(ParseError::InvalidType {
+                name: key.to_string(),
+            }));
+        }
+        Ok(t)
+    }
+}
+
+impl<'a> CiConfigSeed<'a> {
+    fn matrix(
+        self,
+        m: BTreeMap<String, Vec<BTreeMap<String, serde_yaml::Value>>>,
+    ) -> Result<Matrix, ParseError> {
+        let mut matrix = Matrix::default();
+        for (section, entries) in m {
+            match section.as_ref() {
+                "exclude" => {
+                    for entry in &entries {
+                        let selector = self.selector("matrix.exclude", entry)?;
+                        if selector == JobSelector::default() {
+                            // it would exclude every job
+                            return Err(ParseError::InvalidType {
+                                name: "matrix.exclude".to_string(),
+                            });
+                        }
+                        matrix.exclude.push(selector);
+                    }
+                }
+                "include" => {
+                    for entry in &entries {
+                        let selector = self.selector("matrix.include", entry)?;
+                        let missing = |key: &str| ParseError::MissingKey {
+                            key: format!("matrix.include.{}", key),
+                        };
+                        let lang_variant = selector
+                            .lang_variant
+                            .ok_or_else(|| missing(self.language))?;
+                        if let Some(language) = get_language(self.language) {
+                            language.parse_variants(std::slice::from_ref(&lang_variant))?;
+                        }
+                        matrix.include.push(MatrixInclude {
+                            os_version: selector.os_version.ok_or_else(|| missing(self.os))?,
+                            lang_variant,
+                            env: get_env(entry)?,
+                            timeout: get_timeout(entry)?,
+                        });
+                    }
+                }
+                _ => {
+                    return Err(ParseError::UnknownKey {
+                        key: format!("matrix.{}", section),
                     });
                 }
             }
-        } else {
-            return Err(ParseError::InvalidType {
-                name: l.to_string(),
-            });
         }
-    } else {
-        error!("No language {} found!", l);
-        return Err(ParseError::MissingKey { key: l.to_string() });
-    }
-    Ok(result)
-}
-
-pub(crate) fn get_build_os(
-    os: &str,
-    h: &yaml_rust::yaml::Hash,
-) -> Result<Vec<BuildOS>, ParseError> {
-    let mut result = Vec::new();
-    if let Some(k_os) = h.get(&yaml_rust::Yaml::from_str(os)) {
-        if let Some(v_os) = k_os.as_vec() {
-            for v in v_os {
-                if let Some(v_str) = v.as_str() {
-                    result.push(BuildOS {
-                        os_family: os.to_string(),
-                        os_version: v_str.to_string(),
-                    });
-                } else {
-                    return Err(ParseError::GenericError {
-                        msg: "OS array has invalid type".to_string(),
+        Ok(matrix)
+    }
+
+    /// The jobs selected by `no_deploy`, `deploy_only` or `allow_failures`: a
+    /// mapping with the lists of os versions and of language variants, or a
+    /// list of selectors
+    fn deploy_selectors(
+        self,
+        section: &str,
+        v: serde_yaml::Value,
+    ) -> Result<Vec<JobSelector>, ParseError> {
+        let invalid = || ParseError::InvalidType {
+            name: section.to_string(),
+        };
+        let mut selectors = Vec::new();
+        match v {
+            serde_yaml::Value::Mapping(m) => {
+                for (k, list) in &m {
+                    let k = k.as_str().ok_or_else(invalid)?;
+                    if k != self.os && k != self.language {
+                        return Err(ParseError::UnknownKey {
+                            key: format!("{}.{}", section, k),
+                        });
+                    }
+                    for item in list.as_sequence().ok_or_else(invalid)? {
+                        let mut entry = BTreeMap::new();
+                        entry.insert(k.to_string(), item.clone());
+                        selectors.push(self.selector(section, &entry)?);
+                    }
+                }
+            }
+            serde_yaml::Value::Sequence(entries) => {
+                for e in entries {
+                    let entry: BTreeMap<String, serde_yaml::Value> =
+                        serde_yaml::from_value(e).map_err(|_| invalid())?;
+                    let selector = self.selector(section, &entry)?;
+                    if selector == JobSelector::default() {
+                        return Err(invalid());
+                    }
+                    selectors.push(selector);
+                }
+            }
+            _ => return Err(invalid()),
+        }
+        Ok(selectors)
+    }
+
+    /// The os and language keys of a matrix or deploy entry; `env` and
+    /// `timeout` are accepted only in `matrix.include`
+    fn selector(
+        self,
+        section: &str,
+        entry: &BTreeMap<String, serde_yaml::Value>,
+    ) -> Result<JobSelector, ParseError> {
+        let mut selector = JobSelector::default();
+        for (k, v) in entry {
+            let value = || match v {
+                serde_yaml::Value::String(s) => Ok(Some(s.clone())),
+                _ => Err(ParseError::InvalidType {
+                    name: format!("{}.{}", section, k),
+                }),
+            };
+            match k.as_ref() {
+                x if x == self.os => {
+                    selector.os_family = Some(self.os.to_string());
+                    selector.os_version = value()?;
+                }
+                x if x == self.language => {
+                    selector.language = Some(self.language.to_string());
+                    selector.lang_variant = value()?;
+                }
+                "env" | "timeout" if section == "matrix.include" => {}
+                _ => {
+                    return Err(ParseError::UnknownKey {
+                        key: format!("{}.{}", section, k),
                     });
                 }
             }
-        } else {
-            return Err(ParseError::InvalidType {
-                name: os.to_string(),
-            });
         }
-    } else {
-        error!("No os {} found!", os);
-        return Err(ParseError::MissingKey {
-            key: os.to_string(),
-        });
+        Ok(selector)
     }
-    Ok(result)
 }
 
-pub(crate) fn get_update(h: &yaml_rust::yaml::Hash) -> Result<bool, ParseError> {
-    if let Some(k_update) = h.get(&yaml_rust::Yaml::from_str("update")) {
-        if let Some(update) = k_update.as_bool() {
-            debug!("{:?}", update);
-            return Ok(update);
-        } else {
-            error!("update is not a boolean value");
+/// The environment variables of a `matrix.include` entry
+fn get_env(
+    entry: &BTreeMap<String, serde_yaml::Value>,
+) -> Result<BTreeMap<String, String>, ParseError> {
+    let mut env = BTreeMap::new();
+    let mapping = match entry.get("env") {
+        Some(serde_yaml::Value::Mapping(m)) => m,
+        Some(_) => {
             return Err(ParseError::InvalidType {
-                name: "update".to_string(),
+                name: "matrix.include.env".to_string(),
             });
         }
-    } else {
-        info!("update not found: default to false");
-        return Ok(false);
-    }
-}
-
-pub(crate) fn get_no_deploy(
-    h: &yaml_rust::yaml::Hash,
-    jobs: &mut [BuildJob],
-) -> Result<(), ParseError> {
-    if let Some(k_no_deploy) = h.get(&yaml_rust::Yaml::from_str("no_deploy")) {
-        if let Some(v_no_deploy) = k_no_deploy.as_hash() {
-            if let Some(k_lang) = v_no_deploy.get(&yaml_rust::Yaml::from_str("rust")) {
-                if let Some(v_lang) = k_lang.as_vec() {
-                    for v in v_lang {
-                        if let Some(v_str) = v.as_str() {
-                            for j in jobs.iter_mut() {
-                                if j.lang.lang_variant == v_str {
-                                    j.deploy = false
-                                }
-                            }
+        None => return Ok(env),
+    };
+    for (k, v) in mapping {
+        let name = match k.as_str() {
+            Some(name) if is_env_name(name) => name,
+            _ => {
+                return Err(ParseError::InvalidType {
+                    name: "matrix.include.env".to_string(),
+                });
+            }
+        };
+        // the value is single quoted in the build script
+        match v.as_str() {
+            Some(value) if !value.contains('\'') => {
+                env.insert(name.to_string(), value.to_string());
+            }
+            _ => {
+                return Err(ParseError::InvalidType {
+                    name: format!("matrix.include.env.{}", name),
+                });
+            }
+        }
+    }
+    Ok(env)
+}
+
+/// The timeout of a `matrix.include` entry, in minutes
+fn get_timeout(entry: &BTreeMap<String, serde_yaml::Value>) -> Result<Option<u64>, ParseError> {
+    match entry.get("timeout") {
+        Some(v) => match v.as_u64() {
+            Some(t) if t > 0 => Ok(Some(t)),
+            _ => Err(ParseError::InvalidType {
+                name: "matrix.include.timeout".to_string(),
+            }),
+        },
+        None => Ok(None),
+    }
+}
+
+fn is_env_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+impl<'de, 'a> DeserializeSeed<'de> for CiConfigSeed<'a> {
+    type Value = CiConfig;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<CiConfig, D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de, 'a> Visitor<'de> for CiConfigSeed<'a> {
+    type Value = CiConfig;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a mapping")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<CiConfig, A::Error> {
+        let mut schema_version = SCHEMA_VERSION;
+        let mut update = false;
+        let mut timeout = None;
+        let mut stall_timeout = None;
+        let mut no_deploy = Vec::new();
+        let mut deploy_only = None;
+        let mut allow_failures = Vec::new();
+        let mut matrix = Matrix::default();
+        let mut os_versions = None;
+        let mut language_variants = None;
+        let mut build_system = None;
+        while let Some(key) = map.next_key_seed(KeySeed(self))? {
+            match key {
+                Key::SchemaVersion => {
+                    schema_version = self.next_value(&mut map, "schema_version")?
+                }
+                Key::Os => {
+                    self.next_value::<_, YamlString>(&mut map, "os")?;
+                }
+                Key::Language => {
+                    self.next_value::<_, YamlString>(&mut map, "language")?;
+                    if get_language(self.language).is_none() {
+                        return Err(self.fail(ParseError::UnsupportedLanguage {
+                            lang: self.language.to_string(),
+                        }));
+                    }
+                }
+                Key::BuildSystem => {
+                    let bs: YamlString = self.next_value(&mut map, "build_system")?;
+                    if let Some(language) = get_language(self.language) {
+                        if !language.build_systems().contains(&bs.0.as_str()) {
+                            return Err(self.fail(ParseError::UnsupportedBuildSystem {
+                                lang: self.language.to_string(),
+                                build_system: bs.0,
+                            }));
                         }
                     }
+                    build_system = Some(bs.0);
                 }
-            }
-            if let Some(k_os) = v_no_deploy.get(&yaml_rust::Yaml::from_str("FreeBSD")) {
-                if let Some(v_os) = k_os.as_vec() {
-                    for v in v_os {
-                        if let Some(v_str) = v.as_str() {
-                            for j in jobs.iter_mut() {
-                                if j.os.os_version == v_str {
-                                    j.deploy = false
+                Key::Update => update = self.next_value(&mut map, "update")?,
+                Key::Timeout => timeout = Some(self.minutes(&mut map, "timeout")?),
+                Key::StallTimeout => stall_timeout = Some(self.minutes(&mut map, "stall_timeout")?),
+                Key::NoDeploy => {
+                    let v = self.next_value(&mut map, "no_deploy")?;
+                    no_deploy = self
+                        .deploy_selectors("no_deploy", v)
+                        .map_err(|e| self.fail(e))?;
+                }
+                Key::DeployOnly => {
+                    let v = self.next_value(&mut map, "deploy_only")?;
+                    deploy_only = Some(
+                        self.deploy_selectors("deploy_only", v)
+                            .map_err(|e| self.fail(e))?,
+                    );
+                }
+                Key::AllowFailures => {
+                    let v = self.next_value(&mut map, "allow_failures")?;
+                    allow_failures = self
+                        .deploy_selectors("allow_failures", v)
+                        .map_err(|e| self.fail(e))?;
+                }
+                Key::Matrix => {
+                    let m: BTreeMap<String, Vec<BTreeMap<String, serde_yaml::Value>>> =
+                        self.next_value(&mut map, "matrix")?;
+                    matrix = self.matrix(m).map_err(|e| self.fail(e))?;
+                }
+                Key::OsVersions => {
+                    os_versions = Some(into_strings(self.next_value(&mut map, self.os)?));
+                }
+                Key::LanguageVariants => {
+                    let mut variants = into_strings(self.next_value(&mut map, self.language)?);
+                    if let Some(language) = get_language(self.language) {
+                        // the unsupported variants are reported and dropped
+                        variants.retain(|v| {
+                            match language.parse_variants(std::slice::from_ref(v)) {
+                                Ok(_) => true,
+                                Err(e) => {
+                                    self.problems.borrow_mut().push(e);
+                                    false
                                 }
                             }
-                        }
+                        });
                     }
+                    language_variants = Some(variants);
+                }
+                Key::Skipped => {
+                    map.next_value::<de::IgnoredAny>()?;
                 }
             }
-        } else {
-            return Err(ParseError::InvalidType {
-                name: "no_deploy".to_string(),
+            self.current.replace(None);
+        }
+        let is_skipped = |key: &str| self.skipped.iter().any(|k| k == key);
+        let os_versions = match os_versions {
+            Some(v) => v,
+            None if is_skipped(self.os) => Vec::new(),
+            None => {
+                error!("No os {} found!", self.os);
+                return Err(self.fail(ParseError::MissingKey {
+                    key: self.os.to_string(),
+                }));
+            }
+        };
+        let language_variants = match language_variants {
+            Some(v) => v,
+            None if is_skipped(self.language) => Vec::new(),
+            None => {
+                error!("No language {} found!", self.language);
+                return Err(self.fail(ParseError::MissingKey {
+                    key: self.language.to_string(),
+                }));
+            }
+        };
+        Ok(CiConfig {
+            schema_version,
+            os: self.os.to_string(),
+            os_versions,
+            language: self.language.to_string(),
+            language_variants,
+            build_system,
+            update,
+            timeout,
+            stall_timeout,
+            no_deploy,
+            deploy_only,
+            allow_failures,
+            matrix,
+        })
+    }
+}
+
+struct KeySeed<'a>(CiConfigSeed<'a>);
+
+impl<'de, 'a> DeserializeSeed<'de> for KeySeed<'a> {
+    type Value = Key;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Key, D::Error> {
+        deserializer.deserialize_str(self)
+    }
+}
+
+impl<'de, 'a> Visitor<'de> for KeySeed<'a> {
+    type Value = Key;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a key")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Key, E> {
+        self.0.current.replace(Some(v.to_string()));
+        if self.0.skipped.iter().any(|k| k == v) {
+            return Ok(Key::Skipped);
+        }
+        match v {
+            "schema_version" => Ok(Key::SchemaVersion),
+            "os" => Ok(Key::Os),
+            "language" => Ok(Key::Language),
+            "build_system" => Ok(Key::BuildSystem),
+            "update" => Ok(Key::Update),
+            "timeout" => Ok(Key::Timeout),
+            "stall_timeout" => Ok(Key::StallTimeout),
+            "no_deploy" => Ok(Key::NoDeploy),
+            "deploy_only" => Ok(Key::DeployOnly),
+            "allow_failures" => Ok(Key::AllowFailures),
+            "matrix" => Ok(Key::Matrix),
+            x if x == self.0.os => Ok(Key::OsVersions),
+            x if x == self.0.language => Ok(Key::LanguageVariants),
+            _ => {
+                self.0
+                    .failed
+                    .replace(Some(ParseError::UnknownKey { key: v.to_string() }));
+                Err(E::custom(format!(
+                    "unknown key `{}`, expected one of {}, {}, {}",
+                    v,
+                    FIXED_KEYS.join(", "),
+                    self.0.os,
+                    self.0.language
+                )))
+            }
+        }
+    }
+}
+
+fn get_str_key(h: &serde_yaml::Mapping, key: &str) -> Result<String, ParseError> {
+    match h.get(&serde_yaml::Value::from(key)) {
+        Some(serde_yaml::Value::String(s)) => {
+            debug!("{}: {:?}", key, s);
+            Ok(s.to_string())
+        }
+        Some(_) => {
+            error!("{} is not a string", key);
+            Err(ParseError::InvalidType {
+                name: key.to_string(),
+            })
+        }
+        None => {
+            error!("No {} found!", key);
+            Err(ParseError::MissingKey {
+                key: key.to_string(),
+            })
+        }
+    }
+}
+
+/// Check the content of a `.bsd-ci.yml` file and report all the problems
+/// found; `file` is the name used in the diagnostics
+///
+/// The document is read twice: the first pass checks the `schema_version`
+/// and gets the os and the language, that are the names of two other keys.
+/// A key with an error is reported and the second pass is repeated ignoring
+/// it, so the configuration returned with some problems is only partial
+pub(crate) fn check_config(yaml: &str, file: &str) -> (Option<CiConfig>, Vec<YamlDiagnostic>) {
+    let positions = Positions::new(yaml);
+    let diag = |error: ParseError, location: Option<serde_yaml::Location>, keys: &[&str]| {
+        diagnose(
+            &positions,
+            file,
+            error,
+            location.map(|l| (l.line(), l.column())),
+            keys,
+        )
+    };
+    if let Some(&second) = positions.documents.get(1) {
+        let error = ParseError::GenericError {
+            msg: "the file contains more than one YAML document".to_string(),
+        };
+        return (
+            None,
+            vec![diagnose(&positions, file, error, Some(second), FIXED_KEYS)],
+        );
+    }
+    let doc: serde_yaml::Value = match serde_yaml::from_str(yaml) {
+        Ok(doc) => doc,
+        Err(e) => {
+            let error = ParseError::GenericError { msg: e.to_string() };
+            return (None, vec![diag(error, e.location(), FIXED_KEYS)]);
+        }
+    };
+    let h = match doc.as_mapping() {
+        Some(h) => h,
+        None => {
+            error!("The YAML document is not a mapping");
+            let error = ParseError::GenericError {
+                msg: "the YAML document is not a mapping".to_string(),
+            };
+            return (None, vec![diag(error, None, FIXED_KEYS)]);
+        }
+    };
+    let mut diagnostics = Vec::new();
+    let mut skipped = Vec::new();
+    if let Some(v) = h.get(&serde_yaml::Value::from("schema_version")) {
+        match v.as_u64() {
+            Some(version) if version == 0 || version > u64::from(SCHEMA_VERSION) => {
+                let error = ParseError::UnsupportedSchema {
+                    version,
+                    supported: SCHEMA_VERSION,
+                };
+                return (None, vec![diag(error, None, FIXED_KEYS)]);
+            }
+            Some(_) => {}
+            None => {
+                let error = ParseError::InvalidType {
+                    name: "schema_version".to_string(),
+                };
+                diagnostics.push(diag(error, None, FIXED_KEYS));
+                skipped.push("schema_version".to_string());
+            }
+        }
+    }
+    let os = get_str_key(h, "os").map_err(|e| diag(e, None, FIXED_KEYS));
+    let language = get_str_key(h, "language").map_err(|e| diag(e, None, FIXED_KEYS));
+    let (os, language) = match (os, language) {
+        (Ok(os), Ok(language)) => (os, language),
+        (os, language) => {
+            diagnostics.extend(os.err());
+            diagnostics.extend(language.err());
+            return (None, diagnostics);
+        }
+    };
+    let mut keys = FIXED_KEYS.to_vec();
+    keys.push(&os);
+    keys.push(&language);
+    let config = loop {
+        let failed = RefCell::new(None);
+        let problems = RefCell::new(Vec::new());
+        let current = RefCell::new(None);
+        let seed = CiConfigSeed {
+            os: &os,
+            language: &language,
+            failed: &failed,
+            problems: &problems,
+            skipped: &skipped,
+            current: &current,
+        };
+        let rc = serde_yaml::seed::from_str_seed(yaml, seed);
+        // only the problems of the last pass, the most complete one, are kept
+        let problems = problems
+            .into_inner()
+            .into_iter()
+            .map(|e| diag(e, None, &keys));
+        match rc {
+            Ok(config) => {
+                debug!("{:?}", config);
+                diagnostics.extend(problems);
+                break Some(config);
+            }
+            Err(e) => {
+                let error = failed
+                    .into_inner()
+                    .unwrap_or_else(|| ParseError::GenericError { msg: e.to_string() });
+                diagnostics.push(diag(error, e.location(), &keys));
+                match current.into_inner() {
+                    Some(key) if !skipped.contains(&key) => skipped.push(key),
+                    _ => {
+                        diagnostics.extend(problems);
+                        break None;
+                    }
+                }
+            }
+        }
+    };
+    diagnostics.sort_by_key(|d| d.location.unwrap_or((usize::MAX, 0)));
+    (config, diagnostics)
+}
+
+/// Parse the content of a `.bsd-ci.yml` file; `file` is the name used in
+/// the diagnostics
+pub(crate) fn parse_config(yaml: &str, file: &str) -> Result<CiConfig, YamlDiagnostics> {
+    match check_config(yaml, file) {
+        (Some(config), ref diagnostics) if diagnostics.is_empty() => Ok(config),
+        (_, diagnostics) => Err(YamlDiagnostics(diagnostics)),
+    }
+}
+
+/// The keys checked after being read, so that the position reported by
+/// serde_yaml is not the one of the value
+fn is_checked_once_read(name: &str) -> bool {
+    ["matrix", "no_deploy", "deploy_only", "allow_failures"]
+        .iter()
+        .any(|k| name.starts_with(k))
+}
+
+/// Build the diagnostic of an error; `location` is the position reported by
+/// serde_yaml, if any, and `keys` are the valid keys at the top level
+fn diagnose(
+    positions: &Positions,
+    file: &str,
+    error: ParseError,
+    location: Option<(usize, usize)>,
+    keys: &[&str],
+) -> YamlDiagnostic {
+    let key = |path: &str| match positions.key(path) {
+        Some((location, value)) => (Some(location), value),
+        None => (None, None),
+    };
+    let (location, value) = match &error {
+        ParseError::GenericError { .. } => (location, None),
+        ParseError::MissingKey { .. } => (None, None),
+        ParseError::UnsupportedVariant { lang, variant } => {
+            (positions.scalar(lang, variant), Some(variant.clone()))
+        }
+        ParseError::UnknownKey { key: k } => (key(k).0, None),
+        ParseError::InvalidType { name } => {
+            match location.filter(|_| !is_checked_once_read(name)) {
+                Some(location) => (Some(location), positions.scalar_at(location)),
+                None => key(name),
+            }
+        }
+        ParseError::UnsupportedLanguage { .. } => key("language"),
+        ParseError::UnsupportedBuildSystem { .. } => key("build_system"),
+        ParseError::UnsupportedSchema { .. } => key("schema_version"),
+    };
+    let hint = get_hint(&error, value.as_deref(), keys);
+    YamlDiagnostic {
+        file: file.to_string(),
+        location,
+        value,
+        hint,
+        error,
+    }
+}
+
+fn get_hint(error: &ParseError, value: Option<&str>, keys: &[&str]) -> Option<String> {
+    match error {
+        ParseError::GenericError { .. } => None,
+        ParseError::UnsupportedVariant { lang, .. } => {
+            Some(format!("check the spelling of the {} variant", lang))
+        }
+        ParseError::UnsupportedLanguage { .. } => Some(format!(
+            "the supported languages are: {}",
+            get_language_names().join(", ")
+        )),
+        ParseError::UnsupportedBuildSystem { lang, .. } => {
+            match get_language(lang).map(|l| l.build_systems()) {
+                Some(bs) if !bs.is_empty() => Some(format!(
+                    "the build systems supported for {} are: {}",
+                    lang,
+                    bs.join(", ")
+                )),
+                _ => Some(format!("remove build_system, not used by {}", lang)),
+            }
+        }
+        ParseError::MissingKey { key } => Some(match key.as_ref() {
+            "os" => "add the operating system, like `os: FreeBSD`".to_string(),
+            "language" => "add the language, like `language: rust`".to_string(),
+            k if k.starts_with("matrix.include.") => {
+                "every matrix.include entry needs the os version and the language variant"
+                    .to_string()
+            }
+            _ => format!("add the list of {} versions, like `{}: ['...']`", key, key),
+        }),
+        ParseError::UnknownKey { key } => {
+            let (parent, key) = match key.rfind('.') {
+                Some(i) => (Some(&key[..i]), &key[i + 1..]),
+                None => (None, key.as_ref()),
+            };
+            // under no_deploy, deploy_only, allow_failures and in the matrix
+            // entries only the os and the language are valid
+            let valid: Vec<&str> = match parent {
+                Some("matrix") => vec!["include", "exclude"],
+                Some(p) => {
+                    let mut valid: Vec<&str> = keys
+                        .iter()
+                        .filter(|k| !FIXED_KEYS.contains(k))
+                        .cloned()
+                        .collect();
+                    if p == "matrix.include" {
+                        valid.push("env");
+                        valid.push("timeout");
+                    }
+                    valid
+                }
+                None => keys.to_vec(),
+            };
+            match valid.iter().min_by_key(|k| edit_distance(key, k)) {
+                Some(k) if edit_distance(key, k) <= 2 => Some(format!("did you mean `{}`?", k)),
+                _ => Some(format!("valid keys are: {}", valid.join(", "))),
+            }
+        }
+        ParseError::UnsupportedSchema { supported, .. } => Some(format!(
+            "this version of freebsd-ci supports schema_version up to {}",
+            supported
+        )),
+        ParseError::InvalidType { name } => {
+            if let Some(kind) = value.and_then(non_string_kind) {
+                let not_strings = [
+                    "update",
+                    "timeout",
+                    "stall_timeout",
+                    "matrix.include.timeout",
+                    "schema_version",
+                    "no_deploy",
+                    "deploy_only",
+                    "allow_failures",
+                    "matrix",
+                    "matrix.exclude",
+                    "matrix.include.env",
+                ];
+                if !not_strings.contains(&name.as_ref()) {
+                    let value = value.unwrap_or_default();
+                    return Some(format!(
+                        "{}: {} is {}, quote it as '{}'",
+                        name, value, kind, value
+                    ));
+                }
+            }
+            Some(match name.as_ref() {
+                "update" => "update has to be true or false".to_string(),
+                "timeout" | "stall_timeout" | "matrix.include.timeout" => format!(
+                    "{} has to be a positive number of minutes, like `{}: 60`",
+                    name,
+                    name.rsplit('.').next().unwrap_or_default()
+                ),
+                "schema_version" => {
+                    "schema_version has to be an integer, like `schema_version: 1`".to_string()
+                }
+                "no_deploy" | "deploy_only" | "allow_failures" => format!(
+                    "{} has to be a mapping of lists, like `{}: {{ rust: [nightly] }}`, or a list of selectors, like `{}: [{{ FreeBSD: '11.2', rust: stable }}]`",
+                    name, name, name
+                ),
+                "os" => "os has to be a string, like `os: FreeBSD`".to_string(),
+                "language" => "language has to be a string, like `language: rust`".to_string(),
+                "matrix" => "matrix has to be a mapping of lists, like `matrix: { exclude: [...] }`"
+                    .to_string(),
+                "matrix.exclude" => {
+                    "every matrix.exclude entry needs the os version or the language variant"
+                        .to_string()
+                }
+                "matrix.include.env" => {
+                    "env has to be a mapping of variable names to strings, like `env: { CFLAGS: '-O2' }`"
+                        .to_string()
+                }
+                n if n.starts_with("matrix.include.env.") => {
+                    format!("{} has to be a string without single quotes", n)
+                }
+                n if n.starts_with("matrix.") => format!("{} has to be a string", n),
+                _ => format!(
+                    "{} has to be a list of strings, like `{}: ['...']`",
+                    name, name
+                ),
+            })
+        }
+    }
+}
+
+/// The kind of value YAML reads from a plain scalar, if it's not a string
+fn non_string_kind(value: &str) -> Option<&'static str> {
+    if value.parse::<i64>().is_ok() {
+        Some("an integer")
+    } else if value.parse::<f64>().is_ok() {
+        Some("a float")
+    } else if value == "true" || value == "false" {
+        Some("a boolean")
+    } else {
+        None
+    }
+}
+
+/// The positions of the keys and of the scalars of the first YAML document,
+/// from the markers of the parser; `(line, column)` are both 1-based
+#[derive(Default)]
+struct Positions {
+    /// The keys, with their path and their scalar value, if any; nested keys
+    /// are separated by a dot, the items of a list don't add to the path
+    keys: Vec<(String, (usize, usize), Option<String>)>,
+    /// The scalars, with the path of the key they belong to
+    scalars: Vec<(String, String, (usize, usize))>,
+    /// The start of every document in the file
+    documents: Vec<(usize, usize)>,
+    stack: Vec<Node>,
+}
+
+/// A mapping or a list being read, with its path; a mapping keeps the index
+/// of the key waiting for its value
+enum Node {
+    Mapping(String, Option<usize>),
+    Sequence(String),
+}
+
+impl MarkedEventReceiver for Positions {
+    fn on_event(&mut self, event: Event, marker: Marker) {
+        let position = (marker.line(), marker.col() + 1);
+        match event {
+            Event::DocumentStart => self.documents.push(position),
+            _ if self.documents.len() > 1 => {}
+            Event::Scalar(value, style, ..) => {
+                let value = match style {
+                    TScalarStyle::SingleQuoted => format!("'{}'", value),
+                    TScalarStyle::DoubleQuoted => format!("\"{}\"", value),
+                    _ => value,
+                };
+                self.add_node(position, Some(value));
+            }
+            Event::Alias(_) => {
+                self.add_node(position, None);
+            }
+            Event::MappingStart(_) => {
+                let path = self.add_node(position, None);
+                self.stack.push(Node::Mapping(path, None));
+            }
+            Event::SequenceStart(_) => {
+                let path = self.add_node(position, None);
+                self.stack.push(Node::Sequence(path));
+            }
+            Event::MappingEnd | Event::SequenceEnd => {
+                self.stack.pop();
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Positions {
+    fn new(yaml: &str) -> Positions {
+        let mut positions = Positions::default();
+        // the syntax errors are reported by serde_yaml, the positions found
+        // before the error are still useful
+        let _ = Parser::new(yaml.chars()).load(&mut positions, true);
+        positions
+    }
+
+    /// Record a key or a value, returning its path
+    fn add_node(&mut self, position: (usize, usize), value: Option<String>) -> String {
+        let Positions {
+            keys,
+            scalars,
+            stack,
+            ..
+        } = self;
+        let path = match stack.last_mut() {
+            None => return String::new(),
+            Some(Node::Sequence(path)) => path.clone(),
+            Some(Node::Mapping(path, key)) => match key.take() {
+                Some(i) => {
+                    let (path, _, v) = &mut keys[i];
+                    *v = value.clone();
+                    path.clone()
+                }
+                None => {
+                    let name = value.unwrap_or_default();
+                    let name = name.trim_matches(&['\'', '"'][..]);
+                    let path = if path.is_empty() {
+                        name.to_string()
+                    } else {
+                        format!("{}.{}", path, name)
+                    };
+                    keys.push((path.clone(), position, None));
+                    *key = Some(keys.len() - 1);
+                    return path;
+                }
+            },
+        };
+        if let Some(value) = value {
+            scalars.push((path.clone(), value, position));
+        }
+        path
+    }
+
+    /// The position and the value of the first key with the given path
+    fn key(&self, path: &str) -> Option<((usize, usize), Option<String>)> {
+        self.keys
+            .iter()
+            .find(|(p, ..)| p == path)
+            .map(|(_, position, value)| (*position, value.clone()))
+    }
+
+    /// The position of a scalar value, looking first under the key, then in
+    /// the whole document
+    fn scalar(&self, path: &str, value: &str) -> Option<(usize, usize)> {
+        let find = |under: bool| {
+            self.scalars
+                .iter()
+                .find(|(p, v, _)| v == value && (!under || p == path))
+        };
+        find(true)
+            .or_else(|| find(false))
+            .map(|(.., position)| *position)
+    }
+
+    /// The scalar value starting at the given position
+    fn scalar_at(&self, position: (usize, usize)) -> Option<String> {
+        self.scalars
+            .iter()
+            .find(|(.., p)| *p == position)
+            .map(|(_, value, _)| value.clone())
+    }
+}
+
+/// The Levenshtein distance between two strings
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut cur = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == *cb { 0 } else { 1 };
+            cur.push((prev[j] + cost).min(prev[j + 1] + 1).min(cur[j] + 1));
+        }
+        prev = cur;
+    }
+    prev[b.len()]
+}
+
+pub(crate) fn get_build_lang(config: &CiConfig) -> Result<Vec<BuildLang>, ParseError> {
+    match get_language(&config.language) {
+        Some(language) => language.parse_variants(&config.language_variants),
+        None => Err(ParseError::UnsupportedLanguage {
+            lang: config.language.clone(),
+        }),
+    }
+}
+
+/// The build system of the project: the one in the configuration or the
+/// default one of the language, if it has any
+pub(crate) fn get_build_system(config: &CiConfig) -> Option<String> {
+    config.build_system.clone().or_else(|| {
+        get_language(&config.language)
+            .and_then(|l| l.build_systems().first())
+            .map(|bs| bs.to_string())
+    })
+}
+
+pub(crate) fn get_build_os(config: &CiConfig) -> Vec<BuildOS> {
+    config
+        .os_versions
+        .iter()
+        .map(|v| BuildOS {
+            os_family: config.os.clone(),
+            os_version: v.clone(),
+        })
+        .collect()
+}
+
+/// Mark the jobs not to be deployed: the ones selected by `no_deploy` and,
+/// if `deploy_only` is present, the ones not selected by it
+pub(crate) fn get_no_deploy(config: &CiConfig, jobs: &mut [BuildJob]) {
+    for j in jobs.iter_mut() {
+        let excluded = config.no_deploy.iter().any(|s| s.matches(j));
+        let selected = match &config.deploy_only {
+            Some(selectors) => selectors.iter().any(|s| s.matches(j)),
+            None => true,
+        };
+        if excluded || !selected {
+            j.deploy = false
+        }
+    }
+}
+
+/// Mark the jobs whose failure doesn't fail the run
+pub(crate) fn get_allow_failures(config: &CiConfig, jobs: &mut [BuildJob]) {
+    for j in jobs.iter_mut() {
+        j.allow_failure = config.allow_failures.iter().any(|s| s.matches(j));
+    }
+}
+
+/// Apply the matrix rules: the excluded jobs are removed, then the included
+/// ones are added or, if already in the queue, their environment is added
+/// and their timeout is overridden
+fn apply_matrix(config: &CiConfig, jobs: &mut Vec<BuildJob>) -> Result<(), ParseError> {
+    jobs.retain(|j| !config.matrix.exclude.iter().any(|s| s.matches(j)));
+    for i in &config.matrix.include {
+        let selector = JobSelector {
+            os_version: Some(i.os_version.clone()),
+            lang_variant: Some(i.lang_variant.clone()),
+            ..JobSelector::default()
+        };
+        if let Some(j) = jobs.iter_mut().find(|j| selector.matches(j)) {
+            j.env.extend(i.env.clone());
+            j.timeout = i.timeout.or(j.timeout);
+            continue;
+        }
+        let lang = match get_language(&config.language) {
+            Some(language) => language.parse_variants(std::slice::from_ref(&i.lang_variant))?,
+            None => Vec::new(),
+        };
+        for l in lang {
+            jobs.push(BuildJob {
+                lang: l,
+                os: BuildOS {
+                    os_family: config.os.clone(),
+                    os_version: i.os_version.clone(),
+                },
+                deploy: true,
+                allow_failure: false,
+                timeout: i.timeout,
+                env: i.env.clone(),
             });
         }
     }
     Ok(())
 }
 
+/// Build the job queue described by the configuration: every os version
+/// with every language variant, modified by the matrix rules
+pub(crate) fn get_build_queue(config: &CiConfig) -> Result<Vec<BuildJob>, ParseError> {
+    let build_lang = get_build_lang(config)?;
+    let build_os = match config.os.as_ref() {
+        "FreeBSD" => get_build_os(config),
+        _ => {
+            return Err(ParseError::GenericError {
+                msg: format!("os {} not supported", config.os),
+            });
+        }
+    };
+    info!("{:?}", build_lang);
+    info!("{:?}", build_os);
+    let mut build_queue = Vec::new();
+    for o in &build_os {
+        for l in &build_lang {
+            build_queue.push(BuildJob {
+                lang: l.clone(),
+                os: o.clone(),
+                deploy: true,
+                allow_failure: false,
+                timeout: None,
+                env: BTreeMap::new(),
+            });
+            debug!("o {:?} - l {:?}", o, l);
+        }
+    }
+    apply_matrix(config, &mut build_queue)?;
+    get_no_deploy(config, &mut build_queue);
+    get_allow_failures(config, &mut build_queue);
+    Ok(build_queue)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    const HEADER: &str = "os: FreeBSD\nlanguage: rust\n";
+
+    fn parse(yaml: &str) -> Result<CiConfig, ParseError> {
+        parse_config(yaml, ".bsd-ci.yml").map_err(|mut d| d.0.remove(0).error)
+    }
+
+    fn parse_with_header(body: &str) -> Result<CiConfig, ParseError> {
+        parse(&format!("{}{}", HEADER, body))
+    }
+
+    #[test]
+    fn test_parse_config_ok() {
+        let config = parse(
+            "schema_version: 1\nos: FreeBSD\nFreeBSD:\n  - '12.0'\nupdate: true\nlanguage: rust\nrust:\n  - stable\n",
+        )
+        .unwrap();
+        assert_eq!(config.schema_version, 1);
+        assert_eq!(&config.os, "FreeBSD");
+        assert_eq!(config.os_versions, vec!["12.0".to_string()]);
+        assert_eq!(&config.language, "rust");
+        assert_eq!(config.language_variants, vec!["stable".to_string()]);
+        assert!(config.update);
+        assert!(config.no_deploy.is_empty());
+    }
+    #[test]
+    fn test_parse_config_repo() {
+        let config = parse(include_str!("../.bsd-ci.yml")).unwrap();
+        assert_eq!(config.os_versions.len(), 2);
+        assert_eq!(config.language_variants.len(), 3);
+    }
+    #[test]
+    fn test_parse_config_nomapping() {
+        let err = parse("- os\n- FreeBSD\n").unwrap_err();
+        assert!(err.is_genericerror());
+    }
+    #[test]
+    fn test_parse_config_unknown_key() {
+        let err = parse_with_header("rust:\n  - stable\nFreeBSD:\n  - '12.0'\nupdte: true\n")
+            .unwrap_err();
+        match err {
+            ParseError::UnknownKey { key } => assert_eq!(&key, "updte"),
+            _ => panic!("unexpected error {:?}", err),
+        }
+    }
+    #[test]
+    fn test_parse_config_schema_version() {
+        let err = parse("schema_version: 2\nos: FreeBSD\nlanguage: rust\n").unwrap_err();
+        assert!(err.is_unsupportedschema());
+        let err = parse("schema_version: '1'\nos: FreeBSD\nlanguage: rust\n").unwrap_err();
+        assert!(err.is_invalidtype());
+    }
+
+    #[test]
+    fn test_diagnostic_float() {
+        let d = parse_config(
+            "os: FreeBSD\nFreeBSD:\n  - '12.0'\n  - 11.2\nlanguage: rust\nrust: [stable]\n",
+            ".bsd-ci.yml",
+        )
+        .unwrap_err()
+        .0
+        .remove(0);
+        assert!(d.error.is_invalidtype());
+        assert_eq!(d.location, Some((4, 5)));
+        assert_eq!(d.value, Some("11.2".to_string()));
+        assert_eq!(
+            d.hint,
+            Some("FreeBSD: 11.2 is a float, quote it as '11.2'".to_string())
+        );
+        assert!(d.to_string().starts_with(".bsd-ci.yml:4:5: "));
+    }
+    #[test]
+    fn test_diagnostic_flow_sequence() {
+        let d = parse_config(
+            "os: FreeBSD\nFreeBSD: ['12.0', 11.2]\nlanguage: rust\nrust: [stable]\n",
+            "ci.yml",
+        )
+        .unwrap_err()
+        .0
+        .remove(0);
+        assert_eq!(d.location, Some((2, 19)));
+        assert_eq!(d.value, Some("11.2".to_string()));
+    }
+    #[test]
+    fn test_diagnostic_not_a_list() {
+        let d = parse_config(
+            "os: FreeBSD\nFreeBSD: '12.0'\nlanguage: rust\nrust: [stable]\n",
+            "ci.yml",
+        )
+        .unwrap_err()
+        .0
+        .remove(0);
+        assert_eq!(d.location, Some((2, 10)));
+        assert_eq!(
+            d.hint,
+            Some("FreeBSD has to be a list of strings, like `FreeBSD: ['...']`".to_string())
+        );
+    }
+    #[test]
+    fn test_diagnostic_unknown_key() {
+        let d = parse_config(
+            "os: FreeBSD\nFreeBSD: ['12.0']\nlanguage: rust\nrust: [stable]\nupdte: true\n",
+            "ci.yml",
+        )
+        .unwrap_err()
+        .0
+        .remove(0);
+        assert_eq!(d.location, Some((5, 1)));
+        assert_eq!(d.hint, Some("did you mean `update`?".to_string()));
+        let d = parse_config(
+            "os: FreeBSD\nFreeBSD: ['12.0']\nlanguage: rust\nrust: [stable]\nno_deploy:\n  rsut: [beta]\n",
+            "ci.yml",
+        )
+        .unwrap_err()
+        .0
+        .remove(0);
+        assert_eq!(d.location, Some((6, 3)));
+        assert_eq!(d.hint, Some("did you mean `rust`?".to_string()));
+    }
+    #[test]
+    fn test_diagnostic_first_pass() {
+        let d = parse_config("os: FreeBSD\nlanguage: 123\n", "ci.yml")
+            .unwrap_err()
+            .0
+            .remove(0);
+        assert_eq!(d.location, Some((2, 1)));
+        assert_eq!(d.value, Some("123".to_string()));
+        let d = parse_config("os: FreeBSD\n", "ci.yml")
+            .unwrap_err()
+            .0
+            .remove(0);
+        assert_eq!(d.location, None);
+        assert_eq!(
+            d.to_string().lines().next(),
+            Some("ci.yml: Missing key from the YAML: language")
+        );
+    }
+    #[test]
+    fn test_get_build_system() {
+        let c = "os: FreeBSD\nlanguage: c\nFreeBSD: ['12.0']\nc: [clang]\n";
+        let config = parse(c).unwrap();
+        assert_eq!(get_build_system(&config), Some("make".to_string()));
+        let config = parse(&format!("{}build_system: meson\n", c)).unwrap();
+        assert_eq!(get_build_system(&config), Some("meson".to_string()));
+        let config = parse_with_header("FreeBSD: ['12.0']\nrust: [stable]\n").unwrap();
+        assert_eq!(get_build_system(&config), None);
+    }
+    #[test]
+    fn test_diagnostic_build_system() {
+        let d = parse_config(
+            "os: FreeBSD\nlanguage: c\nFreeBSD: ['12.0']\nc: [clang]\nbuild_system: ninja\n",
+            "ci.yml",
+        )
+        .unwrap_err()
+        .0
+        .remove(0);
+        assert!(d.error.is_unsupportedbuildsystem());
+        assert_eq!(d.location, Some((5, 1)));
+        assert_eq!(d.value, Some("ninja".to_string()));
+    }
+    #[test]
+    fn test_diagnostic_variant() {
+        let d = parse_config(
+            "os: FreeBSD\nFreeBSD: ['12.0']\nlanguage: rust\nrust:\n  - stable\n  - stabel\n",
+            "ci.yml",
+        )
+        .unwrap_err()
+        .0
+        .remove(0);
+        assert!(d.error.is_unsupportedvariant());
+        assert_eq!(d.location, Some((6, 5)));
+        assert_eq!(d.value, Some("stabel".to_string()));
+        assert_eq!(
+            d.hint,
+            Some("check the spelling of the rust variant".to_string())
+        );
+        let d = parse_config(
+            "os: FreeBSD\nFreeBSD: ['12.0']\nlanguage: rust\nrust: [stable]\nmatrix:\n  include:\n    - { FreeBSD: '11.2', rust: nigthly }\n",
+            "ci.yml",
+        )
+        .unwrap_err()
+        .0
+        .remove(0);
+        assert!(d.error.is_unsupportedvariant());
+        assert_eq!(d.location, Some((7, 32)));
+    }
+    #[test]
+    fn test_diagnostic_repeated_key() {
+        let d = parse_config(
+            "os: FreeBSD\nFreeBSD: ['12.0']\nlanguage: rust\nno_deploy:\n  rust: [stabel]\nrust: [stable, stabel]\n",
+            "ci.yml",
+        )
+        .unwrap_err()
+        .0
+        .remove(0);
+        assert!(d.error.is_unsupportedvariant());
+        assert_eq!(d.location, Some((6, 16)));
+    }
+    #[test]
+    fn test_diagnostic_documents() {
+        let d = parse_config(
+            "os: FreeBSD\nlanguage: rust\n---\nFreeBSD: ['12.0']\nrust: [stable]\n",
+            "ci.yml",
+        )
+        .unwrap_err()
+        .0
+        .remove(0);
+        assert!(d.error.is_genericerror());
+        assert_eq!(d.location, Some((3, 1)));
+    }
+    #[test]
+    fn test_diagnostic_language() {
+        let d = parse_config(
+            "os: FreeBSD\nFreeBSD: ['12.0']\nlanguage: cobol\ncobol: ['85']\n",
+            "ci.yml",
+        )
+        .unwrap_err()
+        .0
+        .remove(0);
+        assert!(d.error.is_unsupportedlanguage());
+        assert_eq!(d.location, Some((3, 1)));
+        assert_eq!(d.value, Some("cobol".to_string()));
+        assert_eq!(
+            d.hint,
+            Some("the supported languages are: rust, c, go, python".to_string())
+        );
+    }
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance("update", "update"), 0);
+        assert_eq!(edit_distance("updte", "update"), 1);
+        assert_eq!(edit_distance("rsut", "rust"), 2);
+        assert_eq!(edit_distance("", "os"), 2);
+    }
+
     #[test]
     fn test_get_lang_ok() {
-        let docs = yaml_rust::YamlLoader::load_from_str("language: rust").unwrap();
-        let d = docs.first().unwrap();
-        let h = d.as_hash().unwrap();
-        let lang = get_lang(&h).unwrap();
-        assert_eq!("rust", &lang);
+        let config = parse_with_header("rust: [stable]\nFreeBSD: ['12.0']").unwrap();
+        assert_eq!("rust", &config.language);
     }
     #[test]
     fn test_get_lang_norust() {
-        let docs = yaml_rust::YamlLoader::load_from_str("language: ruby").unwrap();
-        let d = docs.first().unwrap();
-        let h = d.as_hash().unwrap();
-        let lang = get_lang(&h).unwrap();
-        assert_ne!("rust", &lang);
+        let config = parse("os: FreeBSD\nFreeBSD: ['12.0']\nlanguage: c\nc: [clang]").unwrap();
+        assert_ne!("rust", &config.language);
+        let lang_err =
+            parse("os: FreeBSD\nFreeBSD: ['12.0']\nlanguage: ruby\nruby: ['2.5']").unwrap_err();
+        assert!(lang_err.is_unsupportedlanguage());
     }
     #[test]
     fn test_get_lang_nolang() {
-        let docs = yaml_rust::YamlLoader::load_from_str("lang: rust").unwrap();
-        let d = docs.first().unwrap();
-        let h = d.as_hash().unwrap();
-        let lang_err: ParseError = get_lang(&h).unwrap_err();
+        let lang_err = parse("os: FreeBSD\nlang: rust").unwrap_err();
         assert!(lang_err.is_missingkey());
     }
     #[test]
     fn test_get_lang_wrongtype() {
-        let docs = yaml_rust::YamlLoader::load_from_str("language: 123").unwrap();
-        let d = docs.first().unwrap();
-        let h = d.as_hash().unwrap();
-        let lang_err: ParseError = get_lang(&h).unwrap_err();
+        let lang_err = parse("os: FreeBSD\nlanguage: 123").unwrap_err();
         assert!(lang_err.is_invalidtype());
     }
     #[test]
     fn test_get_lang_array() {
-        let docs = yaml_rust::YamlLoader::load_from_str("language:\n  - rust\n  - ruby").unwrap();
-        let d = docs.first().unwrap();
-        let h = d.as_hash().unwrap();
-        let lang_err: ParseError = get_lang(&h).unwrap_err();
+        let lang_err = parse("os: FreeBSD\nlanguage:\n  - rust\n  - ruby").unwrap_err();
         assert!(lang_err.is_invalidtype());
     }
 
     #[test]
     fn test_get_os_ok() {
-        let docs = yaml_rust::YamlLoader::load_from_str("os: FreeBSD").unwrap();
-        let d = docs.first().unwrap();
-        let h = d.as_hash().unwrap();
-        let os = get_os(&h).unwrap();
-        assert_eq!("FreeBSD", &os);
+        let config = parse_with_header("rust: [stable]\nFreeBSD: ['12.0']").unwrap();
+        assert_eq!("FreeBSD", &config.os);
     }
     #[test]
     fn test_get_os_norust() {
-        let docs = yaml_rust::YamlLoader::load_from_str("os: Linux").unwrap();
-        let d = docs.first().unwrap();
-        let h = d.as_hash().unwrap();
-        let os = get_os(&h).unwrap();
-        assert_ne!("FreeBSD", &os);
+        let config = parse("os: Linux\nLinux: ['4.20']\nlanguage: rust\nrust: [stable]").unwrap();
+        assert_ne!("FreeBSD", &config.os);
     }
     #[test]
     fn test_get_os_nolang() {
-        let docs = yaml_rust::YamlLoader::load_from_str("os_family: FreeBSD").unwrap();
-        let d = docs.first().unwrap();
-        let h = d.as_hash().unwrap();
-        let os_err: ParseError = get_os(&h).unwrap_err();
+        let os_err = parse("os_family: FreeBSD\nlanguage: rust").unwrap_err();
         assert!(os_err.is_missingkey());
     }
     #[test]
     fn test_get_os_wrongtype() {
-        let docs = yaml_rust::YamlLoader::load_from_str("os: 11.2").unwrap();
-        let d = docs.first().unwrap();
-        let h = d.as_hash().unwrap();
-        let os_err: ParseError = get_os(&h).unwrap_err();
+        let os_err = parse("os: 11.2\nlanguage: rust").unwrap_err();
         assert!(os_err.is_invalidtype());
     }
     #[test]
     fn test_get_os_array() {
-        let docs = yaml_rust::YamlLoader::load_from_str("os:\n  - FreeBSD\n  - osx").unwrap();
-        let d = docs.first().unwrap();
-        let h = d.as_hash().unwrap();
-        let os_err: ParseError = get_os(&h).unwrap_err();
+        let os_err = parse("os:\n  - FreeBSD\n  - osx\nlanguage: rust").unwrap_err();
         assert!(os_err.is_invalidtype());
     }
 
     #[test]
     fn test_get_build_lang_ok() {
-        let docs = yaml_rust::YamlLoader::load_from_str("rust:\n  - nightly\n  - stable").unwrap();
-        let d = docs.first().unwrap();
-        let h = d.as_hash().unwrap();
-        let mut bl = get_build_lang("rust", &h).unwrap().into_iter();
+        let config =
+            parse_with_header("FreeBSD: ['12.0']\nrust:\n  - nightly\n  - stable").unwrap();
+        let mut bl = get_build_lang(&config).unwrap().into_iter();
         assert!(bl.any(|x| x.lang == "rust" && x.lang_variant == "nightly"));
         assert!(bl.any(|x| x.lang == "rust" && x.lang_variant == "stable"));
         assert!(bl
@@ -278,36 +1493,25 @@ mod tests {
     }
     #[test]
     fn test_get_build_lang_noname() {
-        let docs = yaml_rust::YamlLoader::load_from_str("rust:\n  - 1.32\n  - 1.30").unwrap();
-        let d = docs.first().unwrap();
-        let h = d.as_hash().unwrap();
-        let bl_err: ParseError = get_build_lang("rust", &h).unwrap_err();
-        assert!(bl_err.is_genericerror());
+        let bl_err = parse_with_header("FreeBSD: ['12.0']\nrust:\n  - 1.32\n  - 1.30").unwrap_err();
+        assert!(bl_err.is_invalidtype());
     }
     #[test]
     fn test_get_build_lang_noarray() {
-        let docs = yaml_rust::YamlLoader::load_from_str("rust: stable\n").unwrap();
-        let d = docs.first().unwrap();
-        let h = d.as_hash().unwrap();
-        let bl_err: ParseError = get_build_lang("rust", &h).unwrap_err();
+        let bl_err = parse_with_header("FreeBSD: ['12.0']\nrust: stable\n").unwrap_err();
         assert!(bl_err.is_invalidtype());
     }
     #[test]
     fn test_get_build_lang_norust() {
-        let docs = yaml_rust::YamlLoader::load_from_str("ruby:\n  .  stable\n").unwrap();
-        let d = docs.first().unwrap();
-        let h = d.as_hash().unwrap();
-        let bl_err: ParseError = get_build_lang("rust", &h).unwrap_err();
+        let bl_err = parse_with_header("FreeBSD: ['12.0']\n").unwrap_err();
         assert!(bl_err.is_missingkey());
     }
 
     #[test]
     fn test_get_build_os_ok() {
-        let docs =
-            yaml_rust::YamlLoader::load_from_str("FreeBSD:\n  - '11.2'\n  - '12.0'\n").unwrap();
-        let d = docs.first().unwrap();
-        let h = d.as_hash().unwrap();
-        let mut bo = get_build_os("FreeBSD", &h).unwrap().into_iter();
+        let config =
+            parse_with_header("rust: [stable]\nFreeBSD:\n  - '11.2'\n  - '12.0'\n").unwrap();
+        let mut bo = get_build_os(&config).into_iter();
         assert!(bo
             .find(|x| x.os_family == "FreeBSD" && x.os_version == "11.2")
             .is_some());
@@ -320,116 +1524,192 @@ mod tests {
     }
     #[test]
     fn test_get_build_os_nostr() {
-        let docs = yaml_rust::YamlLoader::load_from_str("FreeBSD:\n  - 11.2\n  - 12.0\n").unwrap();
-        let d = docs.first().unwrap();
-        let h = d.as_hash().unwrap();
-        let bo_err = get_build_os("FreeBSD", &h).unwrap_err();
-        assert!(bo_err.is_genericerror());
+        let bo_err =
+            parse_with_header("rust: [stable]\nFreeBSD:\n  - 11.2\n  - 12.0\n").unwrap_err();
+        assert!(bo_err.is_invalidtype());
     }
     #[test]
     fn test_get_build_os_noarray() {
-        let docs = yaml_rust::YamlLoader::load_from_str("FreeBSD: '11.2'\n").unwrap();
-        let d = docs.first().unwrap();
-        let h = d.as_hash().unwrap();
-        let bo_err = get_build_os("FreeBSD", &h).unwrap_err();
+        let bo_err = parse_with_header("rust: [stable]\nFreeBSD: '11.2'\n").unwrap_err();
         assert!(bo_err.is_invalidtype());
     }
     #[test]
     fn test_get_build_os_noos() {
-        let docs = yaml_rust::YamlLoader::load_from_str("Darwin:\n  - '11.2'\n").unwrap();
-        let d = docs.first().unwrap();
-        let h = d.as_hash().unwrap();
-        let bo_err = get_build_os("FreeBSD", &h).unwrap_err();
+        let bo_err = parse_with_header("rust: [stable]\n").unwrap_err();
         assert!(bo_err.is_missingkey());
     }
     #[test]
+    fn test_get_update() {
+        let config = parse_with_header("rust: [stable]\nFreeBSD: ['12.0']\n").unwrap();
+        assert!(!config.update);
+        let update_err =
+            parse_with_header("rust: [stable]\nFreeBSD: ['12.0']\nupdate: 'yes'\n").unwrap_err();
+        assert!(update_err.is_invalidtype());
+    }
+    #[test]
+    fn test_get_no_deploy_unknown_key() {
+        let err = parse_with_header(
+            "rust: [stable]\nFreeBSD: ['12.0']\nno_deploy:\n  ruby:\n    - nightly\n",
+        )
+        .unwrap_err();
+        assert!(err.is_unknownkey());
+        let err = parse_with_header("rust: [stable]\nFreeBSD: ['12.0']\nno_deploy: nightly\n")
+            .unwrap_err();
+        assert!(err.is_invalidtype());
+    }
+    #[test]
     fn test_get_no_deploy() {
-        let docs = yaml_rust::YamlLoader::load_from_str(
-            "no_deploy:\n  rust:\n    - nightly\n    - beta\n  FreeBSD:\n    - '11.2'",
+        let config = parse_with_header(
+            "rust: [stable]\nFreeBSD: ['12.0']\nno_deploy:\n  rust:\n    - nightly\n    - beta\n  FreeBSD:\n    - '11.2'",
         )
         .unwrap();
-        let d = docs.first().unwrap();
-        let h = d.as_hash().unwrap();
         let mut jobs = Vec::new();
-        jobs.push(BuildJob {
-            lang: BuildLang {
-                lang: "rust".to_string(),
-                lang_variant: "stable".to_string(),
-            },
-            os: {
-                BuildOS {
-                    os_family: "FreeBSD".to_string(),
-                    os_version: "12.0".to_string(),
-                }
-            },
-            deploy: true,
-        });
-        jobs.push(BuildJob {
-            lang: BuildLang {
-                lang: "rust".to_string(),
-                lang_variant: "beta".to_string(),
-            },
-            os: {
-                BuildOS {
-                    os_family: "FreeBSD".to_string(),
-                    os_version: "12.0".to_string(),
-                }
-            },
-            deploy: true,
-        });
-        jobs.push(BuildJob {
-            lang: BuildLang {
-                lang: "rust".to_string(),
-                lang_variant: "nightly".to_string(),
-            },
-            os: {
-                BuildOS {
-                    os_family: "FreeBSD".to_string(),
-                    os_version: "12.0".to_string(),
-                }
-            },
-            deploy: true,
-        });
-        jobs.push(BuildJob {
-            lang: BuildLang {
-                lang: "rust".to_string(),
-                lang_variant: "stable".to_string(),
-            },
-            os: {
-                BuildOS {
-                    os_family: "FreeBSD".to_string(),
-                    os_version: "11.2".to_string(),
-                }
-            },
-            deploy: true,
-        });
-        jobs.push(BuildJob {
-            lang: BuildLang {
-                lang: "rust".to_string(),
-                lang_variant: "beta".to_string(),
-            },
-            os: {
-                BuildOS {
-                    os_family: "FreeBSD".to_string(),
-                    os_version: "11.2".to_string(),
-                }
-            },
-            deploy: true,
-        });
-        jobs.push(BuildJob {
-            lang: BuildLang {
-                lang: "rust".to_string(),
-                lang_variant: "nightly".to_string(),
-            },
-            os: {
-                BuildOS {
-                    os_family: "FreeBSD".to_string(),
-                    os_version: "11.2".to_string(),
-                }
-            },
-            deploy: true,
-        });
-        get_no_deploy(h, &mut jobs).unwrap();
+        for os_version in &["12.0", "11.2"] {
+            for variant in &["stable", "beta", "nightly"] {
+                jobs.push(BuildJob {
+                    os: BuildOS {
+                        os_family: "FreeBSD".to_string(),
+                        os_version: os_version.to_string(),
+                    },
+                    ..BuildJob::test("rust", variant)
+                });
+            }
+        }
+        get_no_deploy(&config, &mut jobs);
         assert_eq!(1, jobs.into_iter().filter(|x| x.deploy).count());
     }
+    #[test]
+    fn test_get_build_queue_matrix() {
+        let config = parse_with_header(
+            "FreeBSD: ['11.2', '12.0']\nrust: [stable, nightly]\nmatrix:\n  exclude:\n    - FreeBSD: '11.2'\n      rust: nightly\n  include:\n    - FreeBSD: '13.0'\n      rust: beta\n      env: { RUSTFLAGS: '-D warnings' }\n    - FreeBSD: '12.0'\n      rust: stable\n      env: { CARGO_INCREMENTAL: '0' }\n",
+        )
+        .unwrap();
+        let jobs: Vec<String> = get_build_queue(&config)
+            .unwrap()
+            .iter()
+            .map(|j| format!("{} {:?}", j.to_string(), j.env))
+            .collect();
+        assert_eq!(
+            jobs,
+            vec![
+                "FreeBSD-11_2-rust-stable {}",
+                "FreeBSD-12_0-rust-stable {\"CARGO_INCREMENTAL\": \"0\"}",
+                "FreeBSD-12_0-rust-nightly {}",
+                "FreeBSD-13_0-rust-beta {\"RUSTFLAGS\": \"-D warnings\"}",
+            ]
+        );
+    }
+    #[test]
+    fn test_parse_config_matrix_errors() {
+        let body = "FreeBSD: ['12.0']\nrust: [stable]\nmatrix:\n";
+        let err = parse_with_header(&format!("{}  exclude:\n    - rusty: nightly\n", body));
+        assert!(err.unwrap_err().is_unknownkey());
+        let err = parse_with_header(&format!("{}  exclude:\n    - {{}}\n", body));
+        assert!(err.unwrap_err().is_invalidtype());
+        let err = parse_with_header(&format!("{}  include:\n    - rust: beta\n", body));
+        assert!(err.unwrap_err().is_missingkey());
+        let err = parse_with_header(&format!(
+            "{}  include:\n    - {{ FreeBSD: '12.0', rust: beta, env: {{ 1A: x }} }}\n",
+            body
+        ));
+        assert!(err.unwrap_err().is_invalidtype());
+        let err = parse_with_header(&format!("{}  exclude:\n    - env: {{ A: x }}\n", body));
+        assert!(err.unwrap_err().is_unknownkey());
+    }
+    #[test]
+    fn test_diagnostic_matrix() {
+        let d = parse_config(
+            "os: FreeBSD\nlanguage: rust\nFreeBSD: ['12.0']\nrust: [stable]\nmatrix:\n  exclude:\n    - FreeBSD: 11.2\n",
+            "ci.yml",
+        )
+        .unwrap_err()
+        .0
+        .remove(0);
+        assert!(d.error.is_invalidtype());
+        assert_eq!(d.location, Some((7, 7)));
+        assert_eq!(
+            d.hint,
+            Some("matrix.exclude.FreeBSD: 11.2 is a float, quote it as '11.2'".to_string())
+        );
+    }
+    #[test]
+    fn test_get_no_deploy_selectors() {
+        let body = "FreeBSD: ['11.2', '12.0']\nrust: [stable, nightly]\n";
+        let deployed = |yaml: &str| -> Vec<String> {
+            let config = parse_with_header(&format!("{}{}", body, yaml)).unwrap();
+            get_build_queue(&config)
+                .unwrap()
+                .iter()
+                .filter(|j| j.deploy)
+                .map(|j| j.to_string())
+                .collect()
+        };
+        assert_eq!(
+            deployed("no_deploy:\n  - { FreeBSD: '11.2', rust: stable }\n  - rust: nightly\n"),
+            vec!["FreeBSD-12_0-rust-stable"]
+        );
+        assert_eq!(
+            deployed("deploy_only:\n  - { FreeBSD: '11.2', rust: stable }\n"),
+            vec!["FreeBSD-11_2-rust-stable"]
+        );
+        assert_eq!(
+            deployed("deploy_only:\n  rust: [stable]\nno_deploy:\n  FreeBSD: ['12.0']\n"),
+            vec!["FreeBSD-11_2-rust-stable"]
+        );
+        assert!(deployed("deploy_only: []\n").is_empty());
+    }
+    #[test]
+    fn test_get_no_deploy_selectors_errors() {
+        let body = "FreeBSD: ['12.0']\nrust: [stable]\n";
+        let err = parse_with_header(&format!("{}no_deploy:\n  - rustt: stable\n", body));
+        assert!(err.unwrap_err().is_unknownkey());
+        let err = parse_with_header(&format!("{}deploy_only:\n  - {{}}\n", body));
+        assert!(err.unwrap_err().is_invalidtype());
+        let err = parse_with_header(&format!("{}deploy_only:\n  - stable\n", body));
+        assert!(err.unwrap_err().is_invalidtype());
+    }
+    #[test]
+    fn test_get_allow_failures() {
+        let config = parse_with_header(
+            "FreeBSD: ['11.2', '12.0']\nrust: [stable, nightly]\nallow_failures:\n  rust: [nightly]\n",
+        )
+        .unwrap();
+        let allowed: Vec<String> = get_build_queue(&config)
+            .unwrap()
+            .iter()
+            .filter(|j| j.allow_failure)
+            .map(|j| j.to_string())
+            .collect();
+        assert_eq!(
+            allowed,
+            vec!["FreeBSD-11_2-rust-nightly", "FreeBSD-12_0-rust-nightly"]
+        );
+    }
+    #[test]
+    fn test_get_timeout() {
+        let config = parse_with_header(
+            "FreeBSD: ['12.0']\nrust: [stable, nightly]\ntimeout: 30\nmatrix:\n  include:\n    - { FreeBSD: '12.0', rust: nightly, timeout: 90 }\n",
+        )
+        .unwrap();
+        assert_eq!(config.timeout, Some(30));
+        let timeouts: Vec<Option<u64>> = get_build_queue(&config)
+            .unwrap()
+            .iter()
+            .map(|j| j.timeout)
+            .collect();
+        assert_eq!(timeouts, vec![None, Some(90)]);
+        let err = parse_with_header("FreeBSD: ['12.0']\nrust: [stable]\ntimeout: 0\n");
+        assert!(err.unwrap_err().is_invalidtype());
+        let err = parse_with_header("FreeBSD: ['12.0']\nrust: [stable]\ntimeout: one hour\n");
+        assert!(err.unwrap_err().is_invalidtype());
+    }
+    #[test]
+    fn test_get_stall_timeout() {
+        let config =
+            parse_with_header("FreeBSD: ['12.0']\nrust: [stable]\nstall_timeout: 10\n").unwrap();
+        assert_eq!(config.stall_timeout, Some(10));
+        assert_eq!(config.timeout, None);
+        let err = parse_with_header("FreeBSD: ['12.0']\nrust: [stable]\nstall_timeout: 0\n");
+        assert!(err.unwrap_err().is_invalidtype());
+    }
 }
@@ -0,0 +1,107 @@
+#!/bin/sh -x
+# template variables:
+# language: string : {{ language }}
+#	the language of the build, c
+# language_variant: string : {{ language_variant }}
+#	the compiler, like clang or gcc12
+# build_system: string : {{ build_system }}
+#	the build system, one of make, cmake or meson
+# os_family: string : {{ os_family }}
+#   the os family, like FreeBSD
+# os_version: string : {{ os_version }}
+#   the os version, like 12.0
+# user: string : {{ user }}
+#   the github user name
+# project: string : {{ project }}
+#   the github project name
+# env: map
+#   the environment variables of the job, from matrix.include
+# sha: string : {{ sha }}
+#   the SHA of the commit built (empty if not available)
+# update: boolean : {{ update }}
+# 	if the update has to be performed
+# upload: boolean : {{ upload }}
+# 	if the tarball has to be built, to be uploaded to the github release
+# tarball : string : {{ tarball }}
+#   the tarball file name
+# artifacts_dir : string : {{ artifacts_dir }}
+#   the directory where the tarball has to be left, to be uploaded after the build
+
+export HOME=/root
+export PATH=/sbin:/bin:/usr/sbin:/usr/bin:/usr/local/sbin:/usr/local/bin
+{% for name, value in env %}export {{ name }}='{{ value }}'
+{% endfor %}
+if {{ update }} ; then
+	pkg upgrade -y
+fi
+
+# the C++ compiler is named after the C one (clang15 -> clang++15, gcc12 -> g++12)
+compiler="{{ language_variant }}"
+case $compiler in
+clang*)
+	export CC=$compiler
+	export CXX=clang++${compiler#clang}
+	;;
+gcc*)
+	export CC=$compiler
+	export CXX=g++${compiler#gcc}
+	;;
+esac
+
+tgt_dir="{{ os_family }}-{{ os_version }}-{{ project }}"
+prefix=/tmp/$tgt_dir
+
+cd /mnt
+
+{% if build_system == "cmake" -%}
+if ! cmake -S . -B _build -DCMAKE_BUILD_TYPE=Release -DCMAKE_INSTALL_PREFIX=$prefix ; then
+	exit 1
+fi
+if ! cmake --build _build ; then
+	exit 1
+fi
+if ! ( cd _build && ctest --output-on-failure ) ; then
+	exit 1
+fi
+install_cmd="cmake --install _build"
+{% elif build_system == "meson" -%}
+if ! meson setup _build --buildtype=release --prefix=$prefix ; then
+	exit 1
+fi
+if ! meson compile -C _build ; then
+	exit 1
+fi
+if ! meson test -C _build ; then
+	exit 1
+fi
+install_cmd="meson install -C _build"
+{% else -%}
+if [ -x ./configure ]; then
+	if ! ./configure --prefix=$prefix ; then
+		exit 1
+	fi
+fi
+if ! make ; then
+	exit 1
+fi
+# the test target is check for autotools projects, test for the others
+if make -n check > /dev/null 2>&1 ; then
+	test_target=check
+else
+	test_target=test
+fi
+if ! make $test_target ; then
+	exit 1
+fi
+install_cmd="make install PREFIX=$prefix"
+{%- endif %}
+
+if {{ upload }} ; then
+	tarball="{{ tarball }}"
+	if ! $install_cmd ; then
+		exit 1
+	fi
+	mkdir -p {{ artifacts_dir }}
+	tar zcf {{ artifacts_dir }}/${tarball} -C /tmp $tgt_dir
+fi
+exit 0
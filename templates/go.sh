@@ -0,0 +1,64 @@
+#!/bin/sh -x
+# template variables:
+# language: string : {{ language }}
+#	the language of the build, go
+# language_variant: string : {{ language_variant }}
+#	the go version, like 1.21
+# build_system: string : {{ build_system }}
+#	the build system (empty, not used by go)
+# os_family: string : {{ os_family }}
+#   the os family, like FreeBSD
+# os_version: string : {{ os_version }}
+#   the os version, like 12.0
+# user: string : {{ user }}
+#   the github user name
+# project: string : {{ project }}
+#   the github project name
+# env: map
+#   the environment variables of the job, from matrix.include
+# sha: string : {{ sha }}
+#   the SHA of the commit built (empty if not available)
+# update: boolean : {{ update }}
+# 	if the update has to be performed
+# upload: boolean : {{ upload }}
+# 	if the tarball has to be built, to be uploaded to the github release
+# tarball : string : {{ tarball }}
+#   the tarball file name
+# artifacts_dir : string : {{ artifacts_dir }}
+#   the directory where the tarball has to be left, to be uploaded after the build
+
+export HOME=/root
+export PATH=/sbin:/bin:/usr/sbin:/usr/bin:/usr/local/sbin:/usr/local/bin:/usr/local/go/bin
+export GOPATH=/root/go
+# the module cache fscomp, kept across builds
+export GOMODCACHE=/var/cache/gomod
+{% for name, value in env %}export {{ name }}='{{ value }}'
+{% endfor %}
+if {{ update }} ; then
+	pkg upgrade -y
+fi
+
+cd /mnt
+
+if ! go vet ./... ; then
+	exit 1
+fi
+if ! go build ./... ; then
+	exit 1
+fi
+if ! go test ./... ; then
+	exit 1
+fi
+
+
+if {{ upload }} ; then
+	tgt_dir="{{ os_family }}-{{ os_version }}-{{ project }}"
+	tarball="{{ tarball }}"
+	mkdir $tgt_dir
+	if ! go build -o $tgt_dir/ ./... ; then
+		exit 1
+	fi
+	mkdir -p {{ artifacts_dir }}
+	tar zcf {{ artifacts_dir }}/${tarball} $tgt_dir
+fi
+exit 0
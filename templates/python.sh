@@ -0,0 +1,69 @@
+#!/bin/sh -x
+# template variables:
+# language: string : {{ language }}
+#	the language of the build, python
+# language_variant: string : {{ language_variant }}
+#	the python version, like 3.11
+# build_system: string : {{ build_system }}
+#	the build system (empty, not used by python)
+# os_family: string : {{ os_family }}
+#   the os family, like FreeBSD
+# os_version: string : {{ os_version }}
+#   the os version, like 12.0
+# user: string : {{ user }}
+#   the github user name
+# project: string : {{ project }}
+#   the github project name
+# env: map
+#   the environment variables of the job, from matrix.include
+# sha: string : {{ sha }}
+#   the SHA of the commit built (empty if not available)
+# update: boolean : {{ update }}
+# 	if the update has to be performed
+# upload: boolean : {{ upload }}
+# 	if the tarball has to be built, to be uploaded to the github release
+# tarball : string : {{ tarball }}
+#   the tarball file name
+# artifacts_dir : string : {{ artifacts_dir }}
+#   the directory where the tarball has to be left, to be uploaded after the build
+
+export HOME=/root
+export PATH=/sbin:/bin:/usr/sbin:/usr/bin:/usr/local/sbin:/usr/local/bin
+{% for name, value in env %}export {{ name }}='{{ value }}'
+{% endfor %}
+if {{ update }} ; then
+	pkg upgrade -y
+fi
+
+# a virtualenv per build, outside the project
+venv=/tmp/venv-{{ language_variant }}
+if ! python{{ language_variant }} -m venv $venv ; then
+	exit 1
+fi
+. $venv/bin/activate
+pip install --upgrade pip
+
+cd /mnt
+
+# the test extras are installed, if the project defines them
+if ! pip install ".[test]" ; then
+	exit 1
+fi
+pip install pytest
+if ! python -m pytest ; then
+	exit 1
+fi
+
+
+if {{ upload }} ; then
+	tgt_dir="{{ os_family }}-{{ os_version }}-{{ project }}"
+	tarball="{{ tarball }}"
+	pip install build
+	# the wheel and the sdist
+	if ! python -m build --outdir $tgt_dir ; then
+		exit 1
+	fi
+	mkdir -p {{ artifacts_dir }}
+	tar zcf {{ artifacts_dir }}/${tarball} $tgt_dir
+fi
+exit 0
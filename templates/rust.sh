@@ -7,29 +7,30 @@
 # os_family: string : {{ os_family }}
 #   the os family, like FreeBSD
 # os_version: string : {{ os_version }}
-#   the os family, like 12.0
+#   the os version, like 12.0
 # user: string : {{ user }}
 #   the github user name
 # project: string : {{ project }}
 #   the github project name
+# build_system: string : {{ build_system }}
+#   the build system (empty, not used by rust)
+# env: map
+#   the environment variables of the job, from matrix.include
+# sha: string : {{ sha }}
+#   the SHA of the commit built (empty if not available)
 # update: boolean : {{ update }}
 # 	if the update has to be performed
-# upload: boolean : {{ update }}
-# 	if the upload has to be performed
-# token: string : {{ token }}
-#   the github authorization token (valid only if upload is true)
-# release_id : u64 : {{ release_id }}
-#   the github release to upload the asset to
+# upload: boolean : {{ upload }}
+# 	if the tarball has to be built, to be uploaded to the github release
 # tarball : string : {{ tarball }}
 #   the tarball file name
-# delete_asset : bool : {{ delete_asset }}
-#   the current tarball is already present and has to be deleted before the upload
-# asset_id : u64 : {{ asset_id }}
-#   the asset_id to be deleted
+# artifacts_dir : string : {{ artifacts_dir }}
+#   the directory where the tarball has to be left, to be uploaded after the build
 
 export HOME=/root
 export PATH=/root/.cargo/bin:/sbin:/bin:/usr/sbin:/usr/bin:/usr/local/sbin:/usr/local/bin
-
+{% for name, value in env %}export {{ name }}='{{ value }}'
+{% endfor %}
 if {{ update }} ; then
 	rustup update
 	pkg upgrade -y
@@ -54,16 +55,7 @@ if {{ upload }} ; then
 	tarball="{{ tarball }}"
 	mkdir $tgt_dir
 	mv $HOME/.cargo/bin/{{ project }} $tgt_dir
-	tar zcf ${tarball} $tgt_dir
-	if {{ delete_asset }} ; then
-		curl -H "Authorization: bearer {{ token }}" \
-			-X DELETE \
-			https://api.github.com/repos/{{ user }}/{{ project }}/releases/assets/{{ asset_id }}
-	fi
-	curl -H "Authorization: bearer {{ token }}"\
-		-H "Content-Type: application/gzip" \
-		-X POST \
-		--data-binary @${tarball} \
-		https://uploads.github.com/repos/{{ user }}/{{ project }}/releases/{{ release_id }}/assets\?name\=${tarball}
+	mkdir -p {{ artifacts_dir }}
+	tar zcf {{ artifacts_dir }}/${tarball} $tgt_dir
 fi
 exit 0
@@ -0,0 +1,24 @@
+use std::process::Command;
+
+#[test]
+fn test_lint_other_directory() {
+    // the default templates don't depend on the current directory
+    let dir = std::env::temp_dir().join("freebsd-ci-test-lint-cwd");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(
+        dir.join(".bsd-ci.yml"),
+        "os: FreeBSD\nFreeBSD: ['12.0']\nlanguage: rust\nrust: [stable, nightly]\n",
+    )
+    .unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_freebsd-ci"))
+        .current_dir(&dir)
+        .arg("lint")
+        .output()
+        .unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}